@@ -1,27 +1,212 @@
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    hash::Hash,
+    sync::Arc,
+};
 
 use parking_lot::Mutex;
 use vulkano::{
-    command_buffer::RenderPassBeginInfo,
-    format::ClearValue,
+    command_buffer::{
+        BlitImageInfo,
+        ClearAttachment,
+        ClearRect,
+        ImageBlit,
+        RenderPassBeginInfo,
+        RenderingAttachmentInfo,
+        RenderingInfo,
+    },
+    device::{
+        Device,
+        DeviceOwned,
+    },
+    format::{
+        ClearColorValue,
+        ClearValue,
+        Format,
+    },
     image::{
-        view::ImageView,
+        max_mip_levels,
+        sampler::Filter,
+        sys::RawImage,
+        view::{
+            ImageView,
+            ImageViewCreateInfo,
+            ImageViewType,
+        },
+        AllocateImageError,
         Image,
+        ImageAspects,
+        ImageCreateFlags,
         ImageCreateInfo,
+        ImageLayout,
+        ImageMemory,
+        ImageSubresourceLayers,
+        ImageType,
+        ImageUsage,
+        SampleCount,
     },
-    memory::allocator::{
-        AllocationCreateInfo,
-        MemoryAllocator,
+    memory::{
+        allocator::{
+            AllocationCreateInfo,
+            MemoryAllocatePreference,
+            MemoryAllocator,
+        },
+        DedicatedAllocation,
+        DeviceMemory,
+        MemoryAllocateInfo,
+        MemoryPropertyFlags,
+        ResourceMemory,
+    },
+    pipeline::{
+        graphics::{
+            subpass::PipelineRenderingCreateInfo,
+            viewport::{
+                Scissor,
+                Viewport,
+            },
+        },
+        GraphicsPipeline,
     },
     render_pass::{
+        AttachmentDescription,
+        AttachmentLoadOp,
+        AttachmentReference,
+        AttachmentStoreOp,
         Framebuffer,
         FramebufferCreateInfo,
         RenderPass,
+        RenderPassCreateInfo,
+        Subpass,
+        SubpassDescription,
     },
+    DeviceSize,
+    Validated,
     ValidationError,
+    VulkanError,
+};
+
+use crate::{
+    renderpass::{
+        set_viewport_and_scissor,
+        CmdBuffer,
+    },
+    view::FrameView,
+    GraphicsObjects,
 };
 
-use crate::renderpass::CmdBuffer;
+/// How much larger than requested [`Canvas::recreate_buffers`] makes the images when they have to
+/// be reallocated, so that slowly growing windows don't reallocate every frame
+pub const DEFAULT_GROWTH_FACTOR: f32 = 1.5;
+
+/// How [`Canvas::begin_frame`] sizes the canvas relative to the target it's drawn to
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum CanvasResizePolicy {
+    /// Render at the target's extent
+    #[default]
+    FollowTarget,
+    /// Render at the same extent regardless of the target
+    Fixed([u32; 2]),
+    /// Render at the target's extent multiplied by a factor, below 1 to render at a lower
+    /// resolution and above 1 to supersample
+    Scale(f32),
+    /// Only resize on explicit [`Canvas::recreate_buffers`] calls
+    Manual,
+}
+
+impl CanvasResizePolicy {
+    /// The extent to render at for a target of `target_extent`, at least 1 in each dimension.
+    /// `None` for `Manual`
+    pub fn extent(&self, target_extent: [u32; 2]) -> Option<[u32; 2]> {
+        let extent = match *self {
+            Self::FollowTarget => target_extent,
+            Self::Fixed(extent) => extent,
+            Self::Scale(scale) => {
+                target_extent.map(|dimension| (dimension as f32 * scale).round() as u32)
+            }
+            Self::Manual => return None,
+        };
+
+        Some(extent.map(|dimension| dimension.max(1)))
+    }
+}
+
+/// How a [`CanvasAttachment`]'s images are backed by memory
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CanvasMemory {
+    /// Suballocated from the renderer's memory allocator
+    #[default]
+    Shared,
+    /// A separate allocation per image, which some drivers prefer for large render targets
+    Dedicated,
+    /// Lazily allocated memory, which tiled GPUs may never commit for attachments that don't
+    /// outlive the render pass. Needs `TRANSIENT_ATTACHMENT` usage, and falls back to shared
+    /// memory on devices without a lazily allocated memory type or when allocating it fails
+    Lazy,
+}
+
+/// A named attachment of a [`Canvas`], matched by position to the render pass' attachments
+#[derive(Clone, Debug)]
+pub struct CanvasAttachment {
+    pub name: String,
+    pub image_create_info: ImageCreateInfo,
+    /// What [`RenderPassController::begin_renderpass`] clears the attachment to, required when the
+    /// render pass clears it
+    pub clear_value: Option<ClearValue>,
+    /// Creates the images with a full mip chain for [`Canvas::generate_mips`]. The render pass
+    /// only renders to the first level
+    pub mip_chain: bool,
+    pub memory: CanvasMemory,
+}
+
+impl CanvasAttachment {
+    pub fn new(name: impl Into<String>, image_create_info: ImageCreateInfo) -> Self {
+        Self {
+            name: name.into(),
+            image_create_info,
+            clear_value: None,
+            mip_chain: false,
+            memory: CanvasMemory::default(),
+        }
+    }
+
+    pub fn with_memory(mut self, memory: CanvasMemory) -> Self {
+        self.memory = memory;
+        self
+    }
+
+    pub fn with_mip_chain(mut self) -> Self {
+        self.mip_chain = true;
+        self
+    }
+
+    pub fn with_clear_value(mut self, clear_value: impl Into<ClearValue>) -> Self {
+        self.clear_value = Some(clear_value.into());
+        self
+    }
+}
+
+/// How many layers each canvas image has, and how they're viewed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CanvasLayers {
+    #[default]
+    Single,
+    /// An array of layers, e.g. for shadow cascades or one layer per eye
+    Array(u32),
+    /// Six square, cube compatible layers, sampled through cube views
+    Cube,
+}
+
+impl CanvasLayers {
+    pub fn count(&self) -> u32 {
+        match *self {
+            Self::Single => 1,
+            Self::Array(layers) => layers.max(1),
+            Self::Cube => 6,
+        }
+    }
+}
 
 pub struct Canvas {
     pub inner: Mutex<CanvasInner>,
@@ -29,189 +214,1711 @@ pub struct Canvas {
 
 #[derive(Debug)]
 pub struct CanvasInner {
-    renderpass: Arc<RenderPass>,
-    image_create_infos: Vec<ImageCreateInfo>,
+    /// `None` for canvases rendered with dynamic rendering
+    renderpass: Option<Arc<RenderPass>>,
+    attachments: Vec<CanvasAttachment>,
+    /// For each attachment, the attachment it's resolved into by the render pass, if any
+    resolve_targets: Vec<Option<usize>>,
     num_frames_in_flight: usize,
     current_set: usize,
+    /// Set once [`Canvas::begin_frame`] is used, which then rotates the image sets instead of
+    /// [`Canvas::pass_controller`]
+    frame_managed: bool,
+    /// Keeps at least two image sets so the previous frame's images stay intact
+    keep_history: bool,
+    /// Frames rendered since the images were recreated or the render area changed
+    history_frames: usize,
+    /// The area rendered to, which may be smaller than the images
+    extent: [u32; 2],
+    growth_factor: f32,
+    resize_policy: CanvasResizePolicy,
+    layers: CanvasLayers,
     image_sets: Vec<Vec<Arc<ImageView>>>,
+    /// The first mip level of every layer of each set, as rendered to
+    attachment_sets: Vec<Vec<Arc<ImageView>>>,
     framebuffers: Vec<Arc<Framebuffer>>,
+    /// One framebuffer per layer of each set, empty for single layer canvases
+    layer_framebuffers: Vec<Vec<Arc<Framebuffer>>>,
+    /// Every image of every set, for [`Canvas::memory_usage`]
+    allocations: Vec<ImageAllocation>,
 }
 
 impl Canvas {
-    pub fn empty(
+    /// Creates a canvas without any images, they're allocated by the first resize. Fails if the
+    /// attachments don't match the render pass' in count, format or sample count, if a name is
+    /// used twice, or if an attachment the render pass clears has no clear value
+    pub fn new(
         renderpass: Arc<RenderPass>,
-        image_create_infos: Vec<ImageCreateInfo>,
+        attachments: Vec<CanvasAttachment>,
+    ) -> Result<Arc<Self>, CanvasError> {
+        let descriptions = renderpass.attachments();
+        if descriptions.len() != attachments.len() {
+            return Err(CanvasError::AttachmentCountMismatch {
+                expected: descriptions.len(),
+                provided: attachments.len(),
+            });
+        }
+
+        check_attachments(&attachments)?;
+
+        for (attachment, description) in attachments.iter().zip(descriptions) {
+            if attachment.image_create_info.format != description.format
+                || attachment.image_create_info.samples != description.samples
+            {
+                return Err(CanvasError::AttachmentMismatch(attachment.name.clone()));
+            }
+
+            if clears(description) && attachment.clear_value.is_none() {
+                return Err(CanvasError::MissingClearValue(attachment.name.clone()));
+            }
+        }
+
+        let resolve_targets = resolve_targets(&renderpass);
+
+        Ok(Self::from_parts(
+            Some(renderpass),
+            attachments,
+            resolve_targets,
+        ))
+    }
+
+    /// Creates a canvas without a render pass, rendered with [`Canvas::dynamic_pass`] into any
+    /// combination of its attachments, after starting each frame with [`Canvas::begin_frame`].
+    /// Needs the `dynamic_rendering` feature, which the renderer enables when the device supports
+    /// it. Attachments without a clear value are loaded
+    pub fn dynamic(
+        device: &Arc<Device>,
+        attachments: Vec<CanvasAttachment>,
+    ) -> Result<Arc<Self>, CanvasError> {
+        if !device.enabled_features().dynamic_rendering {
+            return Err(CanvasError::DynamicRenderingUnsupported);
+        }
+
+        check_attachments(&attachments)?;
+
+        let resolve_targets = vec![None; attachments.len()];
+
+        Ok(Self::from_parts(None, attachments, resolve_targets))
+    }
+
+    pub(crate) fn from_parts(
+        renderpass: Option<Arc<RenderPass>>,
+        attachments: Vec<CanvasAttachment>,
+        resolve_targets: Vec<Option<usize>>,
     ) -> Arc<Self> {
         Arc::new(Self {
             inner: Mutex::new(CanvasInner {
                 renderpass,
-                image_create_infos,
+                attachments,
+                resolve_targets,
                 num_frames_in_flight: 0,
                 current_set: 0,
+                frame_managed: false,
+                keep_history: false,
+                history_frames: 0,
+                extent: [0, 0],
+                growth_factor: DEFAULT_GROWTH_FACTOR,
+                resize_policy: CanvasResizePolicy::default(),
+                layers: CanvasLayers::default(),
                 image_sets: Vec::new(),
+                attachment_sets: Vec::new(),
                 framebuffers: Vec::new(),
+                layer_framebuffers: Vec::new(),
+                allocations: Vec::new(),
             }),
         })
     }
 
+    /// Creates a canvas with the same render pass, attachments and settings, but its own images,
+    /// e.g. to render the same passes to another window
+    pub fn duplicate(self: &Arc<Self>) -> Arc<Self> {
+        let inner = self.inner.lock();
+        let canvas = Self::from_parts(
+            inner.renderpass.clone(),
+            inner.attachments.clone(),
+            inner.resolve_targets.clone(),
+        );
+
+        {
+            let mut copy = canvas.inner.lock();
+            copy.keep_history = inner.keep_history;
+            copy.growth_factor = inner.growth_factor;
+            copy.resize_policy = inner.resize_policy;
+            copy.layers = inner.layers;
+        }
+
+        canvas
+    }
+
+    /// Creates a single subpass canvas with the named color attachments and an optional depth
+    /// attachment, rendered with `samples` samples per pixel. When multisampled, each color
+    /// attachment gets a single-sampled resolve attachment named `<name>_resolve`, which
+    /// [`Canvas::resolved_attachment`] returns for the color attachment's name. Colors are cleared
+    /// to transparent black and depth to 1 unless changed with [`Canvas::set_clear_value`].
+    /// Attachments that aren't stored are [`CanvasMemory::Lazy`]
+    pub fn multisampled(
+        device: Arc<Device>,
+        color_attachments: &[(&str, Format)],
+        depth_attachment: Option<(&str, Format)>,
+        samples: SampleCount,
+    ) -> Result<Arc<Self>, CanvasError> {
+        let multisampled = samples != SampleCount::Sample1;
+        let color_reference = |attachment: usize| {
+            Some(AttachmentReference {
+                attachment: attachment as u32,
+                layout: ImageLayout::ColorAttachmentOptimal,
+                ..Default::default()
+            })
+        };
+
+        let mut attachments = Vec::new();
+        let mut canvas_attachments = Vec::new();
+        let mut subpass = SubpassDescription::default();
+
+        for &(name, format) in color_attachments {
+            subpass
+                .color_attachments
+                .push(color_reference(attachments.len()));
+            attachments.push(AttachmentDescription {
+                format,
+                samples,
+                load_op: AttachmentLoadOp::Clear,
+                store_op: match multisampled {
+                    true => AttachmentStoreOp::DontCare,
+                    false => AttachmentStoreOp::Store,
+                },
+                initial_layout: ImageLayout::ColorAttachmentOptimal,
+                final_layout: ImageLayout::ColorAttachmentOptimal,
+                ..Default::default()
+            });
+            canvas_attachments.push(
+                CanvasAttachment::new(
+                    name,
+                    ImageCreateInfo {
+                        image_type: ImageType::Dim2d,
+                        format,
+                        samples,
+                        usage: match multisampled {
+                            true => ImageUsage::COLOR_ATTACHMENT | ImageUsage::TRANSIENT_ATTACHMENT,
+                            false => {
+                                ImageUsage::COLOR_ATTACHMENT
+                                    | ImageUsage::TRANSFER_SRC
+                                    | ImageUsage::SAMPLED
+                            }
+                        },
+                        ..Default::default()
+                    },
+                )
+                .with_clear_value([0.0; 4])
+                .with_memory(match multisampled {
+                    true => CanvasMemory::Lazy,
+                    false => CanvasMemory::Shared,
+                }),
+            );
+        }
+
+        if multisampled {
+            for &(name, format) in color_attachments {
+                subpass
+                    .color_resolve_attachments
+                    .push(color_reference(attachments.len()));
+                attachments.push(AttachmentDescription {
+                    format,
+                    load_op: AttachmentLoadOp::DontCare,
+                    store_op: AttachmentStoreOp::Store,
+                    initial_layout: ImageLayout::ColorAttachmentOptimal,
+                    final_layout: ImageLayout::ColorAttachmentOptimal,
+                    ..Default::default()
+                });
+                canvas_attachments.push(CanvasAttachment::new(
+                    format!("{name}_resolve"),
+                    ImageCreateInfo {
+                        image_type: ImageType::Dim2d,
+                        format,
+                        usage: ImageUsage::COLOR_ATTACHMENT
+                            | ImageUsage::TRANSFER_SRC
+                            | ImageUsage::SAMPLED,
+                        ..Default::default()
+                    },
+                ));
+            }
+        }
+
+        if let Some((name, format)) = depth_attachment {
+            subpass.depth_stencil_attachment = Some(AttachmentReference {
+                attachment: attachments.len() as u32,
+                layout: ImageLayout::DepthStencilAttachmentOptimal,
+                ..Default::default()
+            });
+            attachments.push(AttachmentDescription {
+                format,
+                samples,
+                load_op: AttachmentLoadOp::Clear,
+                store_op: AttachmentStoreOp::DontCare,
+                initial_layout: ImageLayout::DepthStencilAttachmentOptimal,
+                final_layout: ImageLayout::DepthStencilAttachmentOptimal,
+                ..Default::default()
+            });
+            canvas_attachments.push(
+                CanvasAttachment::new(
+                    name,
+                    ImageCreateInfo {
+                        image_type: ImageType::Dim2d,
+                        format,
+                        samples,
+                        usage: ImageUsage::DEPTH_STENCIL_ATTACHMENT
+                            | ImageUsage::TRANSIENT_ATTACHMENT,
+                        ..Default::default()
+                    },
+                )
+                .with_clear_value(1.0)
+                .with_memory(CanvasMemory::Lazy),
+            );
+        }
+
+        let renderpass = RenderPass::new(
+            device,
+            RenderPassCreateInfo {
+                attachments,
+                subpasses: vec![subpass],
+                ..Default::default()
+            },
+        )?;
+
+        Self::new(renderpass, canvas_attachments)
+    }
+
+    /// `None` for dynamic rendering canvases
+    pub fn render_pass(self: &Arc<Self>) -> Option<Arc<RenderPass>> {
+        self.inner.lock().renderpass.clone()
+    }
+
+    /// The area passes render to, starting at the top left corner of the images
     pub fn extent(self: &Arc<Self>) -> [u32; 2] {
+        self.inner.lock().extent
+    }
+
+    /// The size the images were allocated with, at least as large as [`Canvas::extent`]
+    pub fn image_extent(self: &Arc<Self>) -> [u32; 2] {
         let guard = self.inner.lock();
-        match guard.framebuffers.get(0) {
+        match guard.image_sets.first().and_then(|set| set.first()) {
             None => [0, 0],
-            Some(fb) => fb.extent(),
+            Some(view) => {
+                let [width, height, _] = view.image().extent();
+                [width, height]
+            }
+        }
+    }
+
+    /// Sets how much larger than requested [`Canvas::recreate_buffers`] allocates, clamped to at
+    /// least 1
+    pub fn set_growth_factor(self: &Arc<Self>, growth_factor: f32) {
+        self.inner.lock().growth_factor = growth_factor.max(1.0);
+    }
+
+    pub fn resize_policy(self: &Arc<Self>) -> CanvasResizePolicy {
+        self.inner.lock().resize_policy
+    }
+
+    pub fn layers(self: &Arc<Self>) -> CanvasLayers {
+        self.inner.lock().layers
+    }
+
+    /// Changes the number of layers of the images, taking effect when they're next recreated.
+    /// Image sets then hold array or cube views for sampling, the framebuffer covers every layer
+    /// for layered rendering, and [`RenderPassController::layer`] renders to a single layer
+    pub fn set_layers(self: &Arc<Self>, layers: CanvasLayers) {
+        self.inner.lock().layers = layers;
+    }
+
+    /// Changes how the canvas is sized, taking effect on the next [`Canvas::begin_frame`]
+    pub fn set_resize_policy(self: &Arc<Self>, resize_policy: CanvasResizePolicy) {
+        self.inner.lock().resize_policy = resize_policy;
+    }
+
+    /// Resizes the canvas according to its resize policy for a frame drawn to a target of
+    /// `target_extent`, usually the swapchain's image extent. Returns whether the images were
+    /// reallocated. If reallocating fails the canvas is left without images
+    pub fn begin_frame(
+        self: &Arc<Self>,
+        target_extent: [u32; 2],
+        graphics_objects: &GraphicsObjects,
+    ) -> Result<bool, CanvasError> {
+        let mut inner = self.inner.lock();
+        inner.frame_managed = true;
+
+        let recreated = match inner.resize_policy.extent(target_extent) {
+            None => false,
+            Some([width, height]) => inner.recreate_buffers(
+                [width, height, 1],
+                graphics_objects.num_frames_in_flight,
+                graphics_objects.memory_allocator.clone(),
+            )?,
+        };
+
+        inner.advance_set();
+        Ok(recreated)
+    }
+
+    /// The position of the attachment called `name`, as used by the render pass
+    pub fn attachment_index(self: &Arc<Self>, name: &str) -> Result<usize, CanvasError> {
+        self.inner.lock().attachment_index(name)
+    }
+
+    /// The current frame's image for the attachment called `name`. Fails before the images are
+    /// first allocated
+    pub fn attachment(self: &Arc<Self>, name: &str) -> Result<Arc<ImageView>, CanvasError> {
+        let inner = self.inner.lock();
+        let index = inner.attachment_index(name)?;
+        Ok(inner.current_image_set()?[index].clone())
+    }
+
+    /// The current frame's image for the attachment called `name` as it should be read after
+    /// rendering, see [`Canvas::resolved_image_set`]
+    pub fn resolved_attachment(
+        self: &Arc<Self>,
+        name: &str,
+    ) -> Result<Arc<ImageView>, CanvasError> {
+        let inner = self.inner.lock();
+        let index = inner.attachment_index(name)?;
+        Ok(inner.current_resolved_image_set()?[index].clone())
+    }
+
+    /// Changes the value the attachment called `name` is cleared to by default
+    pub fn set_clear_value(
+        self: &Arc<Self>,
+        name: &str,
+        clear_value: impl Into<ClearValue>,
+    ) -> Result<(), CanvasError> {
+        let mut inner = self.inner.lock();
+        let index = inner.attachment_index(name)?;
+        inner.attachments[index].clear_value = Some(clear_value.into());
+        Ok(())
+    }
+
+    /// Fills the mip chain of the current frame's attachment called `name` by successively
+    /// blitting each level into the next, starting from the render area of the first level. The
+    /// command buffer takes care of the layout transitions between the blits. Does nothing for
+    /// attachments without a mip chain, and fails with [`CanvasError::NotAllocated`] before the
+    /// images are first allocated
+    pub fn generate_mips(
+        self: &Arc<Self>,
+        cmd_buf: &mut CmdBuffer,
+        name: &str,
+    ) -> Result<(), CanvasError> {
+        let (image, [width, height]) = self.inner.lock().mip_source(name)?;
+
+        let aspects = image.format().aspects();
+        let filter = match aspects.intersects(ImageAspects::COLOR) {
+            true => Filter::Linear,
+            false => Filter::Nearest,
+        };
+        let level_offsets = |level: u32| {
+            [
+                [0, 0, 0],
+                [(width >> level).max(1), (height >> level).max(1), 1],
+            ]
+        };
+        let subresource = |mip_level| ImageSubresourceLayers {
+            aspects,
+            mip_level,
+            array_layers: 0..image.array_layers(),
+        };
+
+        for level in 1..image.mip_levels() {
+            cmd_buf
+                .blit_image(BlitImageInfo {
+                    regions: [ImageBlit {
+                        src_subresource: subresource(level - 1),
+                        src_offsets: level_offsets(level - 1),
+                        dst_subresource: subresource(level),
+                        dst_offsets: level_offsets(level),
+                        ..Default::default()
+                    }]
+                    .into(),
+                    filter,
+                    ..BlitImageInfo::images(image.clone(), image.clone())
+                })
+                .map_err(|err| CanvasError::Vulkan(Validated::ValidationError(err)))?;
         }
+
+        Ok(())
+    }
+
+    /// Every attachment of the current frame, multisampled ones included. Fails before the images
+    /// are first allocated
+    pub fn current_image_set(self: &Arc<Self>) -> Result<Vec<Arc<ImageView>>, CanvasError> {
+        self.inner.lock().current_image_set().cloned()
+    }
+
+    /// The current frame's attachments as they should be read after rendering, with each
+    /// multisampled attachment replaced by the image it's resolved into. Use this for blits and
+    /// sampling
+    pub fn resolved_image_set(self: &Arc<Self>) -> Result<Vec<Arc<ImageView>>, CanvasError> {
+        self.inner.lock().current_resolved_image_set()
+    }
+
+    /// Keeps the previous frame's images around for temporal effects, even with a single frame in
+    /// flight. Takes effect when the images are next recreated
+    pub fn set_keep_history(self: &Arc<Self>, keep_history: bool) {
+        self.inner.lock().keep_history = keep_history;
+    }
+
+    /// Whether the previous frame's images hold a frame rendered at the current size. History is
+    /// invalidated whenever the images are recreated or the render area changes
+    pub fn history_valid(self: &Arc<Self>) -> bool {
+        self.inner.lock().previous_set().is_some()
     }
 
-    pub fn current_image_set(self: &Arc<Self>) -> Vec<Arc<ImageView>> {
+    /// The images rendered by the frame before the current one, or `None` if there's no valid
+    /// history. The canvas records no dependency on the previous frame itself. Reads are only
+    /// ordered after its writes when both frames are primary `AutoCommandBufferBuilder` command
+    /// buffers submitted in order to the same queue, as [`WindowSurface::present`] does, since
+    /// those begin with a full memory barrier before each image's first use. Anything else, such
+    /// as reading on another queue, needs its own semaphore or barrier. Attachments the render
+    /// pass doesn't store have undefined contents, so read from
+    /// [`Canvas::previous_resolved_image_set`] for multisampled ones
+    ///
+    /// [`WindowSurface::present`]: crate::window_surface::WindowSurface::present
+    pub fn previous_image_set(self: &Arc<Self>) -> Option<Vec<Arc<ImageView>>> {
         let inner = self.inner.lock();
-        inner.image_sets[inner.current_set].clone()
+        inner
+            .previous_set()
+            .map(|set| inner.image_sets[set].clone())
     }
 
-    /* TODO
-    /// Makes sure images can fit the min extent, and if not, recreates them
-    pub fn recreate_buffers(&mut self, min_extent: [u32; 3]) {
+    /// [`Canvas::previous_image_set`] with multisampled attachments replaced by their resolve
+    /// targets, see [`Canvas::resolved_image_set`]
+    pub fn previous_resolved_image_set(self: &Arc<Self>) -> Option<Vec<Arc<ImageView>>> {
+        let inner = self.inner.lock();
+        inner
+            .previous_set()
+            .map(|set| inner.resolved_image_set(set))
+    }
+
+    /// The memory held by the canvas' images, across every image set
+    pub fn memory_usage(self: &Arc<Self>) -> CanvasMemoryUsage {
+        let inner = self.inner.lock();
+        let mut usage = CanvasMemoryUsage {
+            attachments: inner
+                .attachments
+                .iter()
+                .map(|attachment| (attachment.name.clone(), 0))
+                .collect(),
+            images: inner.allocations.len(),
+            ..Default::default()
+        };
+
+        for allocation in &inner.allocations {
+            usage.attachments[allocation.attachment].1 += allocation.bytes;
+
+            let heap_index = allocation.heap_index as usize;
+            if usage.heaps.len() <= heap_index {
+                usage.heaps.resize(heap_index + 1, 0);
+            }
+            usage.heaps[heap_index] += allocation.bytes;
+
+            if allocation.lazy {
+                usage.lazy_bytes += allocation.bytes;
+            }
+        }
+
+        usage
+    }
+
+    /// Makes sure images can fit the min extent, and if not, recreates them rounded up by the
+    /// growth factor. Passes then render into the `min_extent` corner of the images, with
+    /// `min_extent` clamped to the device's largest image dimension. Returns
+    /// whether the images were reallocated. If reallocating fails the canvas is left without
+    /// images
+    pub fn recreate_buffers(
+        self: &Arc<Self>,
+        min_extent: [u32; 3],
+        num_frames_in_flight: usize,
+        allocator: Arc<dyn MemoryAllocator>,
+    ) -> Result<bool, CanvasError> {
+        let mut inner = self.inner.lock();
+        inner.recreate_buffers(min_extent, num_frames_in_flight, allocator)
     }
-    */
 
-    /// Recreate buffers, making sure the images fit the extent precisely
+    /// Recreate buffers, making sure the images fit the extent precisely. If allocating fails the
+    /// canvas is left without images
     pub fn recreate_buffers_exact(
         self: &Arc<Self>,
         exact_extent: [u32; 3],
         num_frames_in_flight: usize,
         allocator: Arc<dyn MemoryAllocator>,
-    ) {
+    ) -> Result<(), CanvasError> {
         let mut inner = self.inner.lock();
-        inner.recreate_buffers_exact(exact_extent, num_frames_in_flight, allocator);
+        inner.recreate_buffers_exact(exact_extent, num_frames_in_flight, allocator)
     }
 
-    pub fn pass_controller(self: &Arc<Self>) -> RenderPassController {
+    /// A controller for rendering the current frame with the canvas' render pass. Unless
+    /// [`Canvas::begin_frame`] is used, this also moves on to the next image set, so it should be
+    /// called once per frame. Fails for dynamic rendering canvases, which are rendered with
+    /// [`Canvas::dynamic_pass`], and before the images are first allocated
+    pub fn pass_controller(self: &Arc<Self>) -> Result<RenderPassController, PassError> {
         let mut inner = self.inner.lock();
-        inner.current_set += 1;
-        inner.current_set %= inner.num_frames_in_flight;
+        let renderpass = inner.renderpass.clone().ok_or(PassError::NoRenderPass)?;
+        if inner.framebuffers.is_empty() {
+            return Err(PassError::NotAllocated);
+        }
+
+        if !inner.frame_managed {
+            inner.advance_set();
+        }
 
-        RenderPassController {
+        let clear_values = inner
+            .attachments
+            .iter()
+            .zip(renderpass.attachments())
+            .map(|(attachment, description)| attachment.clear_value.filter(|_| clears(description)))
+            .collect();
+
+        Ok(RenderPassController {
             current_subpass: None,
+            canvas: self.clone(),
+            clear_values,
+            extent: inner.extent,
             image_views: inner.image_sets[inner.current_set].clone(),
             framebuffer: inner.framebuffers[inner.current_set].clone(),
+            layer_framebuffers: inner.layer_framebuffers[inner.current_set].clone(),
+            bound_pipeline: None,
+        })
+    }
+}
+
+/// Named canvas templates, each instantiated once per key, such as a window. Instances are
+/// created with [`Canvas::duplicate`] on first use, so every key gets its own correctly sized
+/// images and frame rotation
+pub struct CanvasRegistry<K> {
+    templates: HashMap<String, Arc<Canvas>>,
+    instances: HashMap<(K, String), Arc<Canvas>>,
+}
+
+impl<K> Default for CanvasRegistry<K> {
+    fn default() -> Self {
+        Self {
+            templates: HashMap::new(),
+            instances: HashMap::new(),
         }
     }
 }
 
+impl<K: Hash + Eq + Clone> CanvasRegistry<K> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a template under `name`, replacing any previous template and its instances. The
+    /// template itself is never rendered to
+    pub fn register(&mut self, name: impl Into<String>, template: Arc<Canvas>) {
+        let name = name.into();
+        self.instances
+            .retain(|(_, instance_name), _| *instance_name != name);
+        self.templates.insert(name, template);
+    }
+
+    pub fn template(&self, name: &str) -> Option<&Arc<Canvas>> {
+        self.templates.get(name)
+    }
+
+    /// The canvas called `name` for `key`, created from the template if it doesn't exist yet.
+    /// `None` if no template is registered under `name`
+    pub fn get(&mut self, key: K, name: &str) -> Option<Arc<Canvas>> {
+        let template = self.templates.get(name)?;
+        let canvas = self
+            .instances
+            .entry((key, name.to_string()))
+            .or_insert_with(|| template.duplicate());
+
+        Some(canvas.clone())
+    }
+
+    /// Every instance of the template called `name`
+    pub fn instances<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Arc<Canvas>> + 'a {
+        self.instances
+            .iter()
+            .filter(move |((_, instance_name), _)| instance_name == name)
+            .map(|(_, canvas)| canvas)
+    }
+
+    /// Every template with a `None` key, followed by every instance
+    pub fn iter(&self) -> impl Iterator<Item = (&str, Option<&K>, &Arc<Canvas>)> {
+        let templates = self
+            .templates
+            .iter()
+            .map(|(name, canvas)| (name.as_str(), None, canvas));
+        let instances = self
+            .instances
+            .iter()
+            .map(|((key, name), canvas)| (name.as_str(), Some(key), canvas));
+
+        templates.chain(instances)
+    }
+
+    /// Drops every canvas belonging to `key`, e.g. when its window is destroyed
+    pub fn remove_key(&mut self, key: &K) {
+        self.instances
+            .retain(|(instance_key, _), _| instance_key != key);
+    }
+}
+
 pub struct RenderPassController {
-    current_subpass: Option<usize>,
+    current_subpass: Option<u32>,
+    canvas: Arc<Canvas>,
+    clear_values: Vec<Option<ClearValue>>,
+    /// The canvas' render area, which may be smaller than the framebuffer
+    pub extent: [u32; 2],
     pub framebuffer: Arc<Framebuffer>,
     pub image_views: Vec<Arc<ImageView>>,
+    layer_framebuffers: Vec<Arc<Framebuffer>>,
+    bound_pipeline: Option<Arc<GraphicsPipeline>>,
 }
 
 impl RenderPassController {
+    pub fn layer_count(&self) -> u32 {
+        self.framebuffer.layers()
+    }
+
+    /// A controller rendering the same frame's images, but only to `layer`, such as one cube face
+    /// or shadow cascade. `None` for single layer canvases or when `layer` is out of range
+    pub fn layer(&self, layer: u32) -> Option<RenderPassController> {
+        let framebuffer = self.layer_framebuffers.get(layer as usize)?.clone();
+
+        Some(RenderPassController {
+            current_subpass: None,
+            canvas: self.canvas.clone(),
+            clear_values: self.clear_values.clone(),
+            extent: self.extent,
+            framebuffer,
+            image_views: self.image_views.clone(),
+            layer_framebuffers: Vec::new(),
+            bound_pipeline: self.bound_pipeline.clone(),
+        })
+    }
+
+    /// Tells the controller which graphics pipeline the command buffer has bound, e.g. by an
+    /// earlier pass, for [`set_viewport_and_scissor`]
+    pub fn set_bound_pipeline(&mut self, pipeline: Option<Arc<GraphicsPipeline>>) -> &mut Self {
+        self.bound_pipeline = pipeline;
+        self
+    }
+
+    /// Overrides the clear value of the attachment called `name` for this render pass only.
+    /// Ignored for attachments the render pass doesn't clear
+    pub fn set_clear_value(
+        &mut self,
+        name: &str,
+        clear_value: impl Into<ClearValue>,
+    ) -> Result<&mut Self, CanvasError> {
+        let index = self.canvas.attachment_index(name)?;
+        if self.clear_values[index].is_some() {
+            self.clear_values[index] = Some(clear_value.into());
+        }
+
+        Ok(self)
+    }
+
+    /// The subpass commands are currently recorded in, `None` outside the render pass
+    pub fn subpass(&self) -> Option<Subpass> {
+        self.current_subpass
+            .and_then(|index| Subpass::from(self.framebuffer.render_pass().clone(), index))
+    }
+
+    /// Begins the renderpass over the canvas' render area
     pub fn begin_renderpass<'a>(
         &'a mut self,
         cmd_buf: &'a mut CmdBuffer,
-        clear_values: Vec<Option<ClearValue>>,
-    ) -> Result<&mut CmdBuffer, Box<ValidationError>> {
-        match cmd_buf.begin_render_pass(
-            RenderPassBeginInfo {
-                clear_values,
-                ..RenderPassBeginInfo::framebuffer(self.framebuffer.clone())
-            },
-            Default::default(),
-        ) {
-            Ok(val) => {
-                self.current_subpass = Some(0);
-                Ok(val)
-            }
-            Err(err) => Err(err),
-        }
+    ) -> Result<&'a mut CmdBuffer, PassError> {
+        let extent = self.extent;
+        self.begin_renderpass_with_extent(cmd_buf, extent, [0, 0])
     }
 
+    /// Begins the renderpass over part of the canvas, and sets viewport and scissor 0 to cover it,
+    /// unless the pipeline given to [`RenderPassController::set_bound_pipeline`] fixes them
     pub fn begin_renderpass_with_extent<'a>(
         &'a mut self,
         cmd_buf: &'a mut CmdBuffer,
-        clear_values: Vec<Option<ClearValue>>,
         extent: [u32; 2],
         offset: [u32; 2],
-    ) -> Result<&mut CmdBuffer, Box<ValidationError>> {
-        match cmd_buf.begin_render_pass(
+    ) -> Result<&'a mut CmdBuffer, PassError> {
+        if self.current_subpass.is_some() {
+            return Err(PassError::AlreadyActive);
+        }
+
+        cmd_buf.begin_render_pass(
             RenderPassBeginInfo {
-                clear_values,
+                clear_values: self.clear_values.clone(),
                 render_area_extent: extent,
                 render_area_offset: offset,
                 ..RenderPassBeginInfo::framebuffer(self.framebuffer.clone())
             },
             Default::default(),
-        ) {
-            Ok(val) => {
-                self.current_subpass = Some(0);
-                Ok(val)
-            }
-            Err(err) => Err(err),
-        }
+        )?;
+        set_render_area(cmd_buf, extent, offset, self.bound_pipeline.as_deref())?;
+
+        self.current_subpass = Some(0);
+        Ok(cmd_buf)
     }
 
     pub fn next_subpass<'a>(
         &'a mut self,
         cmd_buf: &'a mut CmdBuffer,
-    ) -> Result<&mut CmdBuffer, Box<ValidationError>> {
-        *self
-            .current_subpass
-            .as_mut()
-            .expect("renderpass not active") += 1;
-        cmd_buf.next_subpass(Default::default(), Default::default())
+    ) -> Result<&'a mut CmdBuffer, PassError> {
+        let current = self.current_subpass.ok_or(PassError::NotActive)?;
+        if current + 1 >= self.num_subpasses() {
+            return Err(PassError::SubpassOverflow);
+        }
+
+        cmd_buf.next_subpass(Default::default(), Default::default())?;
+        self.current_subpass = Some(current + 1);
+        Ok(cmd_buf)
     }
 
-    pub fn end_renderpass(
-        self,
-        cmd_buf: &mut CmdBuffer,
-    ) -> Result<&mut CmdBuffer, Box<ValidationError>> {
-        cmd_buf.end_render_pass(Default::default())
+    /// Ends the renderpass, which has to be in its last subpass. On
+    /// [`PassError::SubpassesRemaining`] the render pass stays active, so the remaining subpasses
+    /// can still be moved through before ending it
+    pub fn end_renderpass<'a>(
+        &mut self,
+        cmd_buf: &'a mut CmdBuffer,
+    ) -> Result<&'a mut CmdBuffer, PassError> {
+        let current = self.current_subpass.ok_or(PassError::NotActive)?;
+        let remaining = self.num_subpasses() - 1 - current;
+        if remaining > 0 {
+            return Err(PassError::SubpassesRemaining(remaining));
+        }
+
+        cmd_buf.end_render_pass(Default::default())?;
+        self.current_subpass = None;
+        Ok(cmd_buf)
+    }
+
+    /// Sets viewport and scissor 0 to `view`, and if it asks for it, clears the current
+    /// subpass' attachments inside the view to their clear values. Pipelines drawn in the view
+    /// need dynamic viewport and scissor state. Set the pipeline bound before the view with
+    /// [`RenderPassController::set_bound_pipeline`], so that state it fixes is left alone
+    pub fn begin_view<'a>(
+        &self,
+        cmd_buf: &'a mut CmdBuffer,
+        view: &FrameView,
+    ) -> Result<&'a mut CmdBuffer, PassError> {
+        let subpass = self.subpass().ok_or(PassError::NotActive)?;
+        view.apply(cmd_buf, self.bound_pipeline.as_deref())?;
+        if !view.clear {
+            return Ok(cmd_buf);
+        }
+
+        let description = subpass.subpass_desc();
+        let mut attachments: Vec<_> = description
+            .color_attachments
+            .iter()
+            .enumerate()
+            .filter_map(|(color_attachment, reference)| {
+                let clear_value = match self.clear_values[reference.as_ref()?.attachment as usize] {
+                    Some(ClearValue::Float(color)) => ClearColorValue::Float(color),
+                    Some(ClearValue::Int(color)) => ClearColorValue::Int(color),
+                    Some(ClearValue::Uint(color)) => ClearColorValue::Uint(color),
+                    _ => ClearColorValue::Float([0.0; 4]),
+                };
+
+                Some(ClearAttachment::Color {
+                    color_attachment: color_attachment as u32,
+                    clear_value,
+                })
+            })
+            .collect();
+
+        if let Some(reference) = &description.depth_stencil_attachment {
+            let index = reference.attachment as usize;
+            let (depth, stencil) = match self.clear_values[index] {
+                Some(ClearValue::Depth(depth)) => (depth, 0),
+                Some(ClearValue::Stencil(stencil)) => (1.0, stencil),
+                Some(ClearValue::DepthStencil(depth_stencil)) => depth_stencil,
+                _ => (1.0, 0),
+            };
+
+            let aspects = self.image_views[index].format().aspects();
+            attachments.push(
+                match (
+                    aspects.intersects(ImageAspects::DEPTH),
+                    aspects.intersects(ImageAspects::STENCIL),
+                ) {
+                    (true, true) => ClearAttachment::DepthStencil((depth, stencil)),
+                    (false, true) => ClearAttachment::Stencil(stencil),
+                    _ => ClearAttachment::Depth(depth),
+                },
+            );
+        }
+
+        let rect = ClearRect {
+            offset: view.offset,
+            extent: view.extent,
+            array_layers: 0..self.framebuffer.layers(),
+        };
+
+        Ok(cmd_buf.clear_attachments(
+            attachments.into_iter().collect(),
+            [rect].into_iter().collect(),
+        )?)
+    }
+
+    fn num_subpasses(&self) -> u32 {
+        self.framebuffer.render_pass().subpasses().len() as u32
+    }
+}
+
+/// Renders into a chosen set of a dynamic rendering canvas' attachments, created by
+/// [`Canvas::dynamic_pass`]
+pub struct DynamicPass {
+    canvas: Arc<Canvas>,
+    active: bool,
+    /// The canvas' render area
+    pub extent: [u32; 2],
+    pub color_attachments: Vec<RenderingAttachmentInfo>,
+    pub depth_attachment: Option<RenderingAttachmentInfo>,
+    /// The attachment index of each color attachment, followed by the depth attachment's
+    indices: Vec<usize>,
+    bound_pipeline: Option<Arc<GraphicsPipeline>>,
+}
+
+impl Canvas {
+    /// Prepares rendering into the current frame's attachments called `color_attachments` and
+    /// `depth_attachment`, in that order. Attachments with a clear value are cleared, the others
+    /// keep their contents, and all of them are stored. A frame can render several dynamic passes
+    /// into the same images, so the canvas has to be moved on to the next frame's images with
+    /// [`Canvas::begin_frame`] before its first pass
+    pub fn dynamic_pass(
+        self: &Arc<Self>,
+        color_attachments: &[&str],
+        depth_attachment: Option<&str>,
+    ) -> Result<DynamicPass, CanvasError> {
+        let inner = self.inner.lock();
+        if !inner.frame_managed {
+            return Err(CanvasError::FrameNotBegun);
+        }
+        if inner.attachment_sets.is_empty() {
+            return Err(CanvasError::NotAllocated);
+        }
+
+        let set = &inner.attachment_sets[inner.current_set];
+
+        let mut indices = Vec::new();
+        let mut attachment_info = |name: &str| {
+            let index = inner.attachment_index(name)?;
+            indices.push(index);
+
+            let clear_value = inner.attachments[index].clear_value;
+            Ok(RenderingAttachmentInfo {
+                load_op: match clear_value {
+                    Some(_) => AttachmentLoadOp::Clear,
+                    None => AttachmentLoadOp::Load,
+                },
+                store_op: AttachmentStoreOp::Store,
+                clear_value,
+                ..RenderingAttachmentInfo::image_view(set[index].clone())
+            })
+        };
+
+        let color_attachments = color_attachments
+            .iter()
+            .map(|name| attachment_info(name))
+            .collect::<Result<_, CanvasError>>()?;
+        let depth_attachment = depth_attachment.map(attachment_info).transpose()?;
+
+        Ok(DynamicPass {
+            canvas: self.clone(),
+            active: false,
+            extent: inner.extent,
+            color_attachments,
+            depth_attachment,
+            indices,
+            bound_pipeline: None,
+        })
+    }
+}
+
+impl DynamicPass {
+    /// The attachment formats to build pipelines for this pass with
+    pub fn pipeline_rendering_info(&self) -> PipelineRenderingCreateInfo {
+        let depth_format = self
+            .depth_attachment
+            .as_ref()
+            .map(|attachment| attachment.image_view.format());
+
+        PipelineRenderingCreateInfo {
+            color_attachment_formats: self
+                .color_attachments
+                .iter()
+                .map(|attachment| Some(attachment.image_view.format()))
+                .collect(),
+            depth_attachment_format: depth_format
+                .filter(|format| format.aspects().intersects(ImageAspects::DEPTH)),
+            stencil_attachment_format: depth_format
+                .filter(|format| format.aspects().intersects(ImageAspects::STENCIL)),
+            ..Default::default()
+        }
+    }
+
+    /// Overrides the clear value of the attachment called `name` for this pass only, clearing it
+    /// even if it would otherwise be loaded
+    pub fn set_clear_value(
+        &mut self,
+        name: &str,
+        clear_value: impl Into<ClearValue>,
+    ) -> Result<&mut Self, CanvasError> {
+        let index = self.canvas.attachment_index(name)?;
+        let position = self
+            .indices
+            .iter()
+            .position(|&attachment| attachment == index)
+            .ok_or_else(|| CanvasError::UnknownAttachment(name.to_string()))?;
+
+        let attachment = match self.color_attachments.get_mut(position) {
+            Some(attachment) => attachment,
+            None => self.depth_attachment.as_mut().unwrap(),
+        };
+        attachment.load_op = AttachmentLoadOp::Clear;
+        attachment.clear_value = Some(clear_value.into());
+
+        Ok(self)
+    }
+
+    /// See [`RenderPassController::set_bound_pipeline`]
+    pub fn set_bound_pipeline(&mut self, pipeline: Option<Arc<GraphicsPipeline>>) -> &mut Self {
+        self.bound_pipeline = pipeline;
+        self
+    }
+
+    /// Begins rendering over the canvas' render area, and sets viewport and scissor 0 to cover it
+    /// unless the pipeline given to [`DynamicPass::set_bound_pipeline`] fixes them
+    pub fn begin_rendering<'a>(
+        &'a mut self,
+        cmd_buf: &'a mut CmdBuffer,
+    ) -> Result<&'a mut CmdBuffer, PassError> {
+        if self.active {
+            return Err(PassError::AlreadyActive);
+        }
+
+        let stencil_attachment = self.depth_attachment.clone().filter(|attachment| {
+            attachment
+                .image_view
+                .format()
+                .aspects()
+                .intersects(ImageAspects::STENCIL)
+        });
+        let depth_attachment = self.depth_attachment.clone().filter(|attachment| {
+            attachment
+                .image_view
+                .format()
+                .aspects()
+                .intersects(ImageAspects::DEPTH)
+        });
+
+        cmd_buf.begin_rendering(RenderingInfo {
+            render_area_extent: self.extent,
+            color_attachments: self.color_attachments.iter().cloned().map(Some).collect(),
+            depth_attachment,
+            stencil_attachment,
+            ..Default::default()
+        })?;
+        set_render_area(cmd_buf, self.extent, [0, 0], self.bound_pipeline.as_deref())?;
+
+        self.active = true;
+        Ok(cmd_buf)
+    }
+
+    pub fn end_rendering<'a>(
+        &mut self,
+        cmd_buf: &'a mut CmdBuffer,
+    ) -> Result<&'a mut CmdBuffer, PassError> {
+        if !self.active {
+            return Err(PassError::NotActive);
+        }
+
+        cmd_buf.end_rendering()?;
+        self.active = false;
+        Ok(cmd_buf)
     }
 }
 
+/// Sets viewport and scissor 0 to the render area, where `bound_pipeline` allows it
+fn set_render_area<'a>(
+    cmd_buf: &'a mut CmdBuffer,
+    extent: [u32; 2],
+    offset: [u32; 2],
+    bound_pipeline: Option<&GraphicsPipeline>,
+) -> Result<&'a mut CmdBuffer, Box<ValidationError>> {
+    let viewport = Viewport {
+        offset: offset.map(|value| value as f32),
+        extent: extent.map(|value| value as f32),
+        depth_range: 0.0..=1.0,
+    };
+
+    set_viewport_and_scissor(
+        cmd_buf,
+        viewport,
+        Scissor { offset, extent },
+        bound_pipeline,
+    )
+}
+
 impl CanvasInner {
+    fn advance_set(&mut self) {
+        self.current_set = (self.current_set + 1) % self.image_sets.len().max(1);
+        self.history_frames = self.history_frames.saturating_add(1);
+    }
+
+    fn num_sets(&self, num_frames_in_flight: usize) -> usize {
+        match self.keep_history {
+            true => num_frames_in_flight.max(2),
+            false => num_frames_in_flight,
+        }
+    }
+
+    /// The set rendered by the frame before the current one, if it was rendered at the current
+    /// size into images that are still intact
+    fn previous_set(&self) -> Option<usize> {
+        let num_sets = self.image_sets.len();
+        if num_sets < 2 || self.history_frames < 2 {
+            return None;
+        }
+
+        Some((self.current_set + num_sets - 1) % num_sets)
+    }
+
+    fn current_image_set(&self) -> Result<&Vec<Arc<ImageView>>, CanvasError> {
+        self.image_sets
+            .get(self.current_set)
+            .ok_or(CanvasError::NotAllocated)
+    }
+
+    /// The image [`Canvas::generate_mips`] fills for the attachment called `name`, and the render
+    /// area of its first level
+    fn mip_source(&self, name: &str) -> Result<(Arc<Image>, [u32; 2]), CanvasError> {
+        let index = self.attachment_index(name)?;
+        let image = self.current_resolved_image_set()?[index].image().clone();
+        Ok((image, self.extent))
+    }
+
+    fn current_resolved_image_set(&self) -> Result<Vec<Arc<ImageView>>, CanvasError> {
+        self.current_image_set()?;
+        Ok(self.resolved_image_set(self.current_set))
+    }
+
+    fn resolved_image_set(&self, set: usize) -> Vec<Arc<ImageView>> {
+        let set = &self.image_sets[set];
+        self.resolve_targets
+            .iter()
+            .enumerate()
+            .map(|(index, target)| set[target.unwrap_or(index)].clone())
+            .collect()
+    }
+
+    fn attachment_index(&self, name: &str) -> Result<usize, CanvasError> {
+        self.attachments
+            .iter()
+            .position(|attachment| attachment.name == name)
+            .ok_or_else(|| CanvasError::UnknownAttachment(name.to_string()))
+    }
+
+    pub fn recreate_buffers(
+        &mut self,
+        min_extent: [u32; 3],
+        num_frames_in_flight: usize,
+        allocator: Arc<dyn MemoryAllocator>,
+    ) -> Result<bool, CanvasError> {
+        let max_dimension = allocator
+            .device()
+            .physical_device()
+            .properties()
+            .max_image_dimension2_d;
+        let (extent, image_extent) = grown_extent(
+            [min_extent[0], min_extent[1]],
+            self.growth_factor,
+            max_dimension,
+        );
+
+        let fits = match self.image_sets.first().and_then(|set| set.first()) {
+            None => false,
+            Some(view) => {
+                let current = view.image().extent();
+                (0..2).all(|i| current[i] >= extent[i])
+                    && current[2] >= min_extent[2]
+                    && view.image().array_layers() == self.layers.count()
+            }
+        };
+
+        if fits && self.image_sets.len() == self.num_sets(num_frames_in_flight) {
+            if self.extent != extent {
+                self.extent = extent;
+                self.history_frames = 0;
+            }
+            return Ok(false);
+        }
+
+        self.recreate_buffers_exact(
+            [image_extent[0], image_extent[1], min_extent[2]],
+            num_frames_in_flight,
+            allocator,
+        )?;
+        self.extent = extent;
+
+        Ok(true)
+    }
+
     pub fn recreate_buffers_exact(
         &mut self,
         exact_extent: [u32; 3],
         num_frames_in_flight: usize,
         allocator: Arc<dyn MemoryAllocator>,
-    ) {
+    ) -> Result<(), CanvasError> {
         self.num_frames_in_flight = num_frames_in_flight;
+        self.extent = [exact_extent[0], exact_extent[1]];
+        self.history_frames = 0;
+        self.current_set = 0;
+        self.clear_images();
+
+        let result = self.allocate_sets(exact_extent, num_frames_in_flight, &allocator);
+        if result.is_err() {
+            // Otherwise the sets allocated before the failure would pass for a full allocation
+            self.clear_images();
+        }
+
+        result
+    }
+
+    fn clear_images(&mut self) {
         self.image_sets.clear();
+        self.attachment_sets.clear();
         self.framebuffers.clear();
+        self.layer_framebuffers.clear();
+        self.allocations.clear();
+    }
+
+    fn allocate_sets(
+        &mut self,
+        exact_extent: [u32; 3],
+        num_frames_in_flight: usize,
+        allocator: &Arc<dyn MemoryAllocator>,
+    ) -> Result<(), CanvasError> {
+        // Cube faces have to be square
+        let exact_extent = match self.layers {
+            CanvasLayers::Cube => {
+                let size = exact_extent[0].max(exact_extent[1]);
+                [size, size, exact_extent[2]]
+            }
+            _ => exact_extent,
+        };
+        let layer_count = self.layers.count();
 
-        for _ in 0..self.num_frames_in_flight {
+        for _ in 0..self.num_sets(num_frames_in_flight) {
             let mut set = Vec::new();
+            let mut attachment_views = Vec::new();
+            let mut layer_views = vec![Vec::new(); layer_count as usize];
 
-            for create_info in self.image_create_infos.iter().cloned() {
-                set.push(
-                    ImageView::new_default(
-                        Image::new(
-                            allocator.clone(),
-                            ImageCreateInfo {
-                                extent: exact_extent,
-                                ..create_info
-                            },
-                            AllocationCreateInfo::default(),
-                        )
-                        .unwrap(),
-                    )
-                    .unwrap(),
-                )
+            for (index, attachment) in self.attachments.iter().enumerate() {
+                let mut create_info = ImageCreateInfo {
+                    extent: exact_extent,
+                    array_layers: layer_count,
+                    ..attachment.image_create_info.clone()
+                };
+                if self.layers == CanvasLayers::Cube {
+                    create_info.flags |= ImageCreateFlags::CUBE_COMPATIBLE;
+                }
+                if attachment.mip_chain {
+                    create_info.mip_levels = max_mip_levels(exact_extent);
+                    create_info.usage |= ImageUsage::TRANSFER_SRC | ImageUsage::TRANSFER_DST;
+                }
+
+                let (image, lazy) = allocate_image(allocator, create_info, attachment.memory)?;
+                self.allocations.push(ImageAllocation {
+                    attachment: index,
+                    bytes: image
+                        .memory_requirements()
+                        .iter()
+                        .map(|requirements| requirements.layout.size())
+                        .sum(),
+                    heap_index: heap_index(&image),
+                    lazy,
+                });
+
+                let mip_levels = image.mip_levels();
+                let view = |view_type, array_layers, mip_levels| {
+                    let mut create_info = ImageViewCreateInfo::from_image(&image);
+                    create_info.view_type = view_type;
+                    create_info.subresource_range.array_layers = array_layers;
+                    create_info.subresource_range.mip_levels = mip_levels;
+                    ImageView::new(image.clone(), create_info)
+                };
+
+                // Sampling sees every layer and level, framebuffers only the first level
+                let (sampled_type, attachment_type) = match self.layers {
+                    CanvasLayers::Single => (ImageViewType::Dim2d, ImageViewType::Dim2d),
+                    CanvasLayers::Array(_) => {
+                        (ImageViewType::Dim2dArray, ImageViewType::Dim2dArray)
+                    }
+                    CanvasLayers::Cube => (ImageViewType::Cube, ImageViewType::Dim2dArray),
+                };
+
+                set.push(view(sampled_type, 0..layer_count, 0..mip_levels)?);
+                attachment_views.push(view(attachment_type, 0..layer_count, 0..1)?);
+                if layer_count > 1 {
+                    for (layer, views) in layer_views.iter_mut().enumerate() {
+                        let layer = layer as u32;
+                        views.push(view(ImageViewType::Dim2d, layer..layer + 1, 0..1)?);
+                    }
+                }
             }
 
-            self.framebuffers.push(
-                Framebuffer::new(
-                    self.renderpass.clone(),
-                    FramebufferCreateInfo {
-                        attachments: set.clone(),
-                        ..Default::default()
-                    },
-                )
-                .unwrap(),
-            );
+            if let Some(renderpass) = &self.renderpass {
+                let framebuffer = |attachments| {
+                    Framebuffer::new(
+                        renderpass.clone(),
+                        FramebufferCreateInfo {
+                            attachments,
+                            ..Default::default()
+                        },
+                    )
+                };
+
+                self.framebuffers
+                    .push(framebuffer(attachment_views.clone())?);
+                self.layer_framebuffers.push(match layer_count {
+                    1 => Vec::new(),
+                    _ => layer_views
+                        .into_iter()
+                        .map(framebuffer)
+                        .collect::<Result<_, _>>()?,
+                });
+            }
 
+            self.attachment_sets.push(attachment_views);
             self.image_sets.push(set);
         }
 
         //println!("recreate_buffers_exact:\n{:#?}", self)
+        Ok(())
+    }
+}
+
+/// The render area and image extent [`Canvas::recreate_buffers`] uses for `min_extent`. The render
+/// area is clamped to the device's largest image dimension, and the images are grown by
+/// `growth_factor` without exceeding it
+fn grown_extent(
+    min_extent: [u32; 2],
+    growth_factor: f32,
+    max_dimension: u32,
+) -> ([u32; 2], [u32; 2]) {
+    let extent = min_extent.map(|dimension| dimension.min(max_dimension));
+    let image_extent = extent.map(|dimension| {
+        ((dimension as f32 * growth_factor).ceil() as u32).clamp(dimension, max_dimension)
+    });
+
+    (extent, image_extent)
+}
+
+/// Memory held by a canvas' images, from [`Canvas::memory_usage`]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CanvasMemoryUsage {
+    /// The bytes allocated for each attachment, in attachment order
+    pub attachments: Vec<(String, DeviceSize)>,
+    /// The bytes allocated from each of the device's memory heaps, by heap index
+    pub heaps: Vec<DeviceSize>,
+    /// The part of the total in lazily allocated memory, which may never be committed
+    pub lazy_bytes: DeviceSize,
+    pub images: usize,
+}
+
+impl CanvasMemoryUsage {
+    pub fn bytes(&self) -> DeviceSize {
+        self.attachments.iter().map(|(_, bytes)| bytes).sum()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct ImageAllocation {
+    attachment: usize,
+    bytes: DeviceSize,
+    heap_index: u32,
+    lazy: bool,
+}
+
+/// Creates an image backed as `memory` asks, and whether it ended up in lazily allocated memory
+fn allocate_image(
+    allocator: &Arc<dyn MemoryAllocator>,
+    create_info: ImageCreateInfo,
+    memory: CanvasMemory,
+) -> Result<(Arc<Image>, bool), CanvasError> {
+    if memory == CanvasMemory::Lazy {
+        if let Some(image) = allocate_lazy(allocator.device(), create_info.clone()) {
+            return Ok((image, true));
+        }
+    }
+
+    let allocate_preference = match memory {
+        CanvasMemory::Dedicated => MemoryAllocatePreference::AlwaysAllocate,
+        _ => MemoryAllocatePreference::Unknown,
+    };
+
+    let image = Image::new(
+        allocator.clone(),
+        create_info,
+        AllocationCreateInfo {
+            allocate_preference,
+            ..Default::default()
+        },
+    )?;
+
+    Ok((image, false))
+}
+
+/// Binds an image to its own lazily allocated memory, which the standard allocator never hands
+/// out. `None` if the device has no lazily allocated memory type the image can use, or if creating
+/// or allocating it fails, in which case the caller falls back to shared memory
+fn allocate_lazy(device: &Arc<Device>, create_info: ImageCreateInfo) -> Option<Arc<Image>> {
+    let raw_image = RawImage::new(device.clone(), create_info).ok()?;
+    let requirements = raw_image.memory_requirements()[0];
+
+    let memory_types = &device.physical_device().memory_properties().memory_types;
+    let memory_type_index = (0..memory_types.len() as u32).find(|&index| {
+        requirements.memory_type_bits & (1 << index) != 0
+            && memory_types[index as usize]
+                .property_flags
+                .intersects(MemoryPropertyFlags::LAZILY_ALLOCATED)
+    })?;
+
+    let memory = DeviceMemory::allocate(
+        device.clone(),
+        MemoryAllocateInfo {
+            allocation_size: requirements.layout.size(),
+            memory_type_index,
+            dedicated_allocation: Some(DedicatedAllocation::Image(&raw_image)),
+            ..Default::default()
+        },
+    )
+    .ok()?;
+
+    let image = raw_image
+        .bind_memory([ResourceMemory::new_dedicated(memory)])
+        .ok()?;
+
+    Some(Arc::new(image))
+}
+
+/// The memory heap backing `image`
+fn heap_index(image: &Image) -> u32 {
+    let memory_type_index = match image.memory() {
+        ImageMemory::Normal(memory) => memory[0].device_memory().memory_type_index(),
+        _ => unreachable!("canvas images are always bound to normal memory"),
+    };
+
+    image
+        .device()
+        .physical_device()
+        .memory_properties()
+        .memory_types[memory_type_index as usize]
+        .heap_index
+}
+
+/// The checks shared by [`Canvas::new`] and [`Canvas::dynamic`]
+fn check_attachments(attachments: &[CanvasAttachment]) -> Result<(), CanvasError> {
+    for (index, attachment) in attachments.iter().enumerate() {
+        if attachments[..index]
+            .iter()
+            .any(|other| other.name == attachment.name)
+        {
+            return Err(CanvasError::DuplicateName(attachment.name.clone()));
+        }
+
+        if attachment.mip_chain && attachment.image_create_info.samples != SampleCount::Sample1 {
+            return Err(CanvasError::MultisampledMipChain(attachment.name.clone()));
+        }
+
+        if attachment.memory == CanvasMemory::Lazy {
+            if !attachment
+                .image_create_info
+                .usage
+                .intersects(ImageUsage::TRANSIENT_ATTACHMENT)
+            {
+                return Err(CanvasError::LazyNotTransient(attachment.name.clone()));
+            }
+
+            // Transient images can't be used for the transfers a mip chain needs
+            if attachment.mip_chain {
+                return Err(CanvasError::LazyMipChain(attachment.name.clone()));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn clears(description: &AttachmentDescription) -> bool {
+    description.load_op == AttachmentLoadOp::Clear
+        || description.stencil_load_op == Some(AttachmentLoadOp::Clear)
+}
+
+#[derive(Debug)]
+pub enum CanvasError {
+    /// The render pass has a different number of attachments than the canvas
+    AttachmentCountMismatch {
+        expected: usize,
+        provided: usize,
+    },
+    /// The attachment's format or sample count differs from the render pass'
+    AttachmentMismatch(String),
+    DuplicateName(String),
+    /// Multisampled images can't have more than one mip level
+    MultisampledMipChain(String),
+    /// The device doesn't have the `dynamic_rendering` feature enabled
+    DynamicRenderingUnsupported,
+    /// Lazily allocated attachments need `TRANSIENT_ATTACHMENT` usage
+    LazyNotTransient(String),
+    /// Lazily allocated attachments can't have a mip chain, which needs transfer usage
+    LazyMipChain(String),
+    /// The render pass clears the attachment, but it has no clear value
+    MissingClearValue(String),
+    /// The canvas' images haven't been allocated yet
+    NotAllocated,
+    /// Dynamic passes need the canvas' frames to be started with [`Canvas::begin_frame`]
+    FrameNotBegun,
+    UnknownAttachment(String),
+    /// An image couldn't be created, e.g. because the device ran out of memory
+    Allocation(Validated<AllocateImageError>),
+    Vulkan(Validated<VulkanError>),
+}
+
+impl fmt::Display for CanvasError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AttachmentCountMismatch { expected, provided } => write!(
+                f,
+                "the render pass has {expected} attachments, but {provided} were provided"
+            ),
+            Self::AttachmentMismatch(name) => write!(
+                f,
+                "attachment \"{name}\" doesn't match the render pass' format or sample count"
+            ),
+            Self::DuplicateName(name) => write!(f, "attachment \"{name}\" is named twice"),
+            Self::MultisampledMipChain(name) => {
+                write!(
+                    f,
+                    "attachment \"{name}\" is multisampled and can't have a mip chain"
+                )
+            }
+            Self::MissingClearValue(name) => {
+                write!(f, "attachment \"{name}\" is cleared but has no clear value")
+            }
+            Self::DynamicRenderingUnsupported => {
+                write!(f, "the device doesn't support dynamic rendering")
+            }
+            Self::LazyNotTransient(name) => write!(
+                f,
+                "attachment \"{name}\" is lazily allocated but isn't a transient attachment"
+            ),
+            Self::LazyMipChain(name) => write!(
+                f,
+                "attachment \"{name}\" is lazily allocated and can't have a mip chain"
+            ),
+            Self::NotAllocated => write!(f, "the canvas' images haven't been allocated"),
+            Self::FrameNotBegun => {
+                write!(f, "the canvas' frame wasn't started with begin_frame")
+            }
+            Self::UnknownAttachment(name) => write!(f, "the canvas has no attachment \"{name}\""),
+            Self::Allocation(err) => write!(f, "failed to allocate canvas image: {err}"),
+            Self::Vulkan(err) => write!(f, "canvas operation failed: {err}"),
+        }
+    }
+}
+
+impl Error for CanvasError {}
+
+impl From<Validated<AllocateImageError>> for CanvasError {
+    fn from(err: Validated<AllocateImageError>) -> Self {
+        Self::Allocation(err)
+    }
+}
+
+impl From<Validated<VulkanError>> for CanvasError {
+    fn from(err: Validated<VulkanError>) -> Self {
+        Self::Vulkan(err)
+    }
+}
+
+/// Misuse of a canvas' render pass, or of a [`RenderPassController`]
+#[derive(Debug)]
+pub enum PassError {
+    /// The canvas is rendered with dynamic rendering and has no render pass
+    NoRenderPass,
+    /// The canvas' images haven't been allocated yet
+    NotAllocated,
+    /// The render pass hasn't been begun
+    NotActive,
+    /// The render pass was begun twice
+    AlreadyActive,
+    /// `next_subpass` was called in the last subpass
+    SubpassOverflow,
+    /// The render pass was ended with this many subpasses left
+    SubpassesRemaining(u32),
+    Validation(Box<ValidationError>),
+}
+
+impl fmt::Display for PassError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoRenderPass => write!(f, "the canvas has no render pass"),
+            Self::NotAllocated => write!(f, "the canvas' images haven't been allocated"),
+            Self::NotActive => write!(f, "the render pass isn't active"),
+            Self::AlreadyActive => write!(f, "the render pass is already active"),
+            Self::SubpassOverflow => write!(f, "the render pass has no next subpass"),
+            Self::SubpassesRemaining(remaining) => write!(
+                f,
+                "the render pass was ended with {remaining} subpasses remaining"
+            ),
+            Self::Validation(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl Error for PassError {}
+
+impl From<Box<ValidationError>> for PassError {
+    fn from(err: Box<ValidationError>) -> Self {
+        Self::Validation(err)
+    }
+}
+
+/// Finds which attachment each attachment of `renderpass` is resolved into, following the last
+/// subpass that resolves it
+fn resolve_targets(renderpass: &RenderPass) -> Vec<Option<usize>> {
+    let mut targets = vec![None; renderpass.attachments().len()];
+
+    for subpass in renderpass.subpasses() {
+        let color_resolves = subpass
+            .color_attachments
+            .iter()
+            .zip(&subpass.color_resolve_attachments);
+        let depth_resolve = subpass
+            .depth_stencil_attachment
+            .iter()
+            .zip(&subpass.depth_stencil_resolve_attachment)
+            .map(|(attachment, resolve)| (Some(attachment), Some(resolve)));
+
+        for (attachment, resolve) in color_resolves
+            .map(|(attachment, resolve)| (attachment.as_ref(), resolve.as_ref()))
+            .chain(depth_resolve)
+        {
+            if let (Some(attachment), Some(resolve)) = (attachment, resolve) {
+                targets[attachment.attachment as usize] = Some(resolve.attachment as usize);
+            }
+        }
+    }
+
+    targets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unallocated_canvas() -> Arc<Canvas> {
+        let attachments = vec![
+            CanvasAttachment::new("color", ImageCreateInfo::default()),
+            CanvasAttachment::new("depth", ImageCreateInfo::default()),
+        ];
+        Canvas::from_parts(None, attachments, vec![None, None])
+    }
+
+    #[test]
+    fn unallocated_canvas_has_no_images() {
+        let canvas = unallocated_canvas();
+
+        assert!(matches!(
+            canvas.attachment("color"),
+            Err(CanvasError::NotAllocated)
+        ));
+        assert!(matches!(
+            canvas.resolved_attachment("depth"),
+            Err(CanvasError::NotAllocated)
+        ));
+        assert!(matches!(
+            canvas.current_image_set(),
+            Err(CanvasError::NotAllocated)
+        ));
+        assert!(matches!(
+            canvas.resolved_image_set(),
+            Err(CanvasError::NotAllocated)
+        ));
+        assert!(canvas.previous_image_set().is_none());
+        assert_eq!(canvas.image_extent(), [0, 0]);
+    }
+
+    #[test]
+    fn unallocated_canvas_has_no_mips_to_generate() {
+        let canvas = unallocated_canvas();
+        let inner = canvas.inner.lock();
+
+        assert!(matches!(
+            inner.mip_source("color"),
+            Err(CanvasError::NotAllocated)
+        ));
+        assert!(matches!(
+            inner.mip_source("normal"),
+            Err(CanvasError::UnknownAttachment(_))
+        ));
+    }
+
+    #[test]
+    fn unknown_attachment_is_reported_before_allocation() {
+        let canvas = unallocated_canvas();
+
+        assert!(matches!(
+            canvas.attachment("normal"),
+            Err(CanvasError::UnknownAttachment(name)) if name == "normal"
+        ));
+        assert_eq!(canvas.attachment_index("depth").unwrap(), 1);
+    }
+
+    #[test]
+    fn resize_policy_extent_is_never_zero() {
+        assert_eq!(
+            CanvasResizePolicy::Fixed([0, 720]).extent([1920, 1080]),
+            Some([1, 720])
+        );
+        assert_eq!(
+            CanvasResizePolicy::FollowTarget.extent([0, 0]),
+            Some([1, 1])
+        );
+        assert_eq!(
+            CanvasResizePolicy::Scale(0.001).extent([100, 1000]),
+            Some([1, 1])
+        );
+        assert_eq!(CanvasResizePolicy::Manual.extent([0, 0]), None);
+    }
+
+    #[test]
+    fn grown_extent_stays_within_the_device_limit() {
+        assert_eq!(grown_extent([100, 10], 1.5, 16384), ([100, 10], [150, 15]));
+        assert_eq!(
+            grown_extent([12000, 100], 1.5, 16384),
+            ([12000, 100], [16384, 150])
+        );
+        assert_eq!(
+            grown_extent([20000, 1], 1.5, 16384),
+            ([16384, 1], [16384, 2])
+        );
+    }
+
+    #[test]
+    fn resize_policy_scale_rounds() {
+        assert_eq!(
+            CanvasResizePolicy::Scale(0.5).extent([1001, 999]),
+            Some([501, 500])
+        );
+        assert_eq!(
+            CanvasResizePolicy::Scale(1.5).extent([1920, 1080]),
+            Some([2880, 1620])
+        );
     }
 }
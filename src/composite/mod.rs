@@ -0,0 +1,560 @@
+use std::{
+    error::Error,
+    fmt,
+    marker::PhantomData,
+    sync::Arc,
+};
+
+use parking_lot::Mutex;
+use vulkano::{
+    command_buffer::{
+        BlitImageInfo,
+        ClearColorImageInfo,
+        CopyImageInfo,
+    },
+    format::{
+        ClearColorValue,
+        Format,
+        NumericFormat,
+    },
+    image::{
+        sampler::Filter,
+        AllocateImageError,
+        Image,
+        ImageCreateInfo,
+        ImageType,
+        ImageUsage,
+    },
+    memory::allocator::AllocationCreateInfo,
+    Validated,
+    ValidationError,
+};
+
+use crate::{
+    canvas::{
+        Canvas,
+        CanvasError,
+    },
+    frame::FrameContext,
+    renderpass::{
+        CmdBuffer,
+        ErrorHandler,
+        HaltPolicy,
+        RenderPass,
+    },
+    GraphicsObjects,
+};
+
+/// How a [`CompositePass`] fits the canvas onto its target
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ScaleMode {
+    /// Fill the whole target, distorting the canvas if the aspect ratios differ
+    #[default]
+    Stretch,
+    /// Scale uniformly to fit inside the target, centered, with borders on two sides
+    Letterbox,
+    /// Scale by the largest whole number that fits, centered, with nearest filtering. Falls back
+    /// to `Letterbox` when the canvas is larger than the target
+    Integer,
+    /// Scale uniformly to cover the whole target, cutting off the edges of the canvas
+    Crop,
+}
+
+/// The corners of the source and target rectangles a composite blits between
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CompositeRegion {
+    pub src_offsets: [[u32; 2]; 2],
+    pub dst_offsets: [[u32; 2]; 2],
+}
+
+impl CompositeRegion {
+    /// Whether the region leaves part of a `dst_extent` target uncovered
+    pub fn has_border(&self, dst_extent: [u32; 2]) -> bool {
+        self.dst_offsets != [[0, 0], dst_extent]
+    }
+}
+
+impl ScaleMode {
+    /// Where a `src_extent` canvas ends up on a `dst_extent` target
+    pub fn region(&self, src_extent: [u32; 2], dst_extent: [u32; 2]) -> CompositeRegion {
+        let full_src = [[0, 0], src_extent];
+        let full_dst = [[0, 0], dst_extent];
+        if src_extent.contains(&0) || dst_extent.contains(&0) {
+            return CompositeRegion {
+                src_offsets: full_src,
+                dst_offsets: full_dst,
+            };
+        }
+
+        let fit = f64::min(
+            dst_extent[0] as f64 / src_extent[0] as f64,
+            dst_extent[1] as f64 / src_extent[1] as f64,
+        );
+
+        // The part of `outer` taken up by `extent`, centered
+        let centered = |outer: [u32; 2], extent: [u32; 2]| {
+            let extent = [extent[0].clamp(1, outer[0]), extent[1].clamp(1, outer[1])];
+            let offset = [(outer[0] - extent[0]) / 2, (outer[1] - extent[1]) / 2];
+            [offset, [offset[0] + extent[0], offset[1] + extent[1]]]
+        };
+        let scaled =
+            |scale: f64| src_extent.map(|dimension| (dimension as f64 * scale).round() as u32);
+
+        match self {
+            Self::Stretch => CompositeRegion {
+                src_offsets: full_src,
+                dst_offsets: full_dst,
+            },
+            Self::Integer if fit >= 1.0 => CompositeRegion {
+                src_offsets: full_src,
+                dst_offsets: centered(dst_extent, scaled(fit.floor())),
+            },
+            Self::Letterbox | Self::Integer => CompositeRegion {
+                src_offsets: full_src,
+                dst_offsets: centered(dst_extent, scaled(fit)),
+            },
+            Self::Crop => {
+                let cover = f64::max(
+                    dst_extent[0] as f64 / src_extent[0] as f64,
+                    dst_extent[1] as f64 / src_extent[1] as f64,
+                );
+
+                CompositeRegion {
+                    src_offsets: centered(
+                        src_extent,
+                        dst_extent.map(|dimension| (dimension as f64 / cover).round() as u32),
+                    ),
+                    dst_offsets: full_dst,
+                }
+            }
+        }
+    }
+}
+
+/// What a [`CompositePass`] does when only one of the canvas and target formats is sRGB
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SrgbConversion {
+    /// Copies the encoded values, treating non-sRGB images as already holding sRGB encoded
+    /// colors. This is what presentation engines assume for swapchains in the sRGB color space,
+    /// so the window shows the same colors whichever of the two is sRGB
+    #[default]
+    KeepEncoding,
+    /// Lets the blit decode and encode, for non-sRGB canvases holding linear colors
+    Convert,
+}
+
+/// Shared data a [`CompositePass`] can find its target image in
+pub trait CompositeTarget {
+    /// The image to composite onto, usually the swapchain image acquired for the frame
+    fn composite_target(&self) -> Arc<Image>;
+}
+
+/// The intermediate images a [`CompositePass`] keeps between frames for
+/// [`SrgbConversion::KeepEncoding`], one per frame-in-flight slot. Clones share the images, so
+/// passes that are recreated every frame can keep reusing them. Slots are only safe to reuse once
+/// the frame that last used them has finished, so use one per target window, whose frame slots
+/// are fenced independently of other windows
+#[derive(Clone, Default)]
+pub struct CompositeStaging {
+    slots: Arc<Mutex<Vec<Option<Arc<Image>>>>>,
+}
+
+impl CompositeStaging {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A `format` image of `extent` for the frame in the `frame_index` frame-in-flight slot,
+    /// reused from the last frame in that slot unless the format or extent changed
+    fn image(
+        &self,
+        graphics_objects: &GraphicsObjects,
+        frame_index: usize,
+        format: Format,
+        extent: [u32; 2],
+    ) -> Result<Arc<Image>, CompositeError> {
+        let mut slots = self.slots.lock();
+        let num_slots = graphics_objects.num_frames_in_flight.max(1);
+        slots.resize(num_slots, None);
+        let slot = frame_index % num_slots;
+
+        if let Some(image) = &slots[slot] {
+            if image.format() == format && image.extent() == [extent[0], extent[1], 1] {
+                return Ok(image.clone());
+            }
+        }
+
+        let image = Image::new(
+            graphics_objects.memory_allocator.clone(),
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                format,
+                extent: [extent[0], extent[1], 1],
+                usage: ImageUsage::TRANSFER_SRC | ImageUsage::TRANSFER_DST,
+                ..Default::default()
+            },
+            AllocationCreateInfo::default(),
+        )?;
+
+        slots[slot] = Some(image.clone());
+        Ok(image)
+    }
+}
+
+/// Blits a canvas attachment onto the frame's target image, scaled according to a [`ScaleMode`]
+pub struct CompositePass<S> {
+    pub canvas: Arc<Canvas>,
+    /// Read through [`Canvas::resolved_attachment`], so multisampled attachments work too
+    pub attachment: String,
+    pub mode: ScaleMode,
+    /// Ignored for [`ScaleMode::Integer`], which always uses nearest filtering
+    pub filter: Filter,
+    /// What the parts of the target the canvas doesn't cover are cleared to
+    pub border_color: [f32; 4],
+    pub srgb_conversion: SrgbConversion,
+    pub staging: CompositeStaging,
+    error_handler: Option<ErrorHandler<CompositeError>>,
+    shared: PhantomData<fn(&S)>,
+}
+
+impl<S> CompositePass<S> {
+    pub fn new(canvas: Arc<Canvas>, attachment: impl Into<String>) -> Self {
+        Self {
+            canvas,
+            attachment: attachment.into(),
+            mode: ScaleMode::default(),
+            filter: Filter::Linear,
+            border_color: [0.0, 0.0, 0.0, 1.0],
+            srgb_conversion: SrgbConversion::default(),
+            staging: CompositeStaging::default(),
+            error_handler: None,
+            shared: PhantomData,
+        }
+    }
+
+    pub fn with_mode(mut self, mode: ScaleMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn with_border_color(mut self, border_color: [f32; 4]) -> Self {
+        self.border_color = border_color;
+        self
+    }
+
+    pub fn with_srgb_conversion(mut self, srgb_conversion: SrgbConversion) -> Self {
+        self.srgb_conversion = srgb_conversion;
+        self
+    }
+
+    /// Shares staging images with earlier passes, for passes that are recreated every frame
+    pub fn with_staging(mut self, staging: CompositeStaging) -> Self {
+        self.staging = staging;
+        self
+    }
+
+    /// Handles the errors of the pass when it runs in a render system. The frame is still
+    /// submitted after an error, since its image has been acquired, and without a handler the
+    /// error is dropped
+    pub fn with_error_handler(
+        mut self,
+        error_handler: impl FnMut(CompositeError) + Send + 'static,
+    ) -> Self {
+        self.error_handler = Some(Box::new(error_handler));
+        self
+    }
+
+    /// Records the composite onto `target`, which needs `TRANSFER_DST` usage, for the frame in
+    /// the `frame_index` frame-in-flight slot. While the canvas has no images the whole target is
+    /// cleared to the border color
+    pub fn record(
+        &self,
+        graphics_objects: &GraphicsObjects,
+        frame_index: usize,
+        cmd_buf: &mut CmdBuffer,
+        target: Arc<Image>,
+    ) -> Result<(), CompositeError> {
+        let Some(source) = self.source()? else {
+            cmd_buf.clear_color_image(ClearColorImageInfo {
+                clear_value: ClearColorValue::Float(self.border_color),
+                ..ClearColorImageInfo::image(target)
+            })?;
+            return Ok(());
+        };
+
+        let [width, height, _] = target.extent();
+        let dst_extent = [width, height];
+        let region = self.mode.region(self.canvas.extent(), dst_extent);
+
+        if region.has_border(dst_extent) {
+            cmd_buf.clear_color_image(ClearColorImageInfo {
+                clear_value: ClearColorValue::Float(self.border_color),
+                ..ClearColorImageInfo::image(target.clone())
+            })?;
+        }
+
+        let filter = match self.mode {
+            ScaleMode::Integer => Filter::Nearest,
+            _ => self.filter,
+        };
+        let blit = |src_image: Arc<Image>, dst_image: Arc<Image>, dst_offsets: [[u32; 2]; 2]| {
+            let mut blit = BlitImageInfo::images(src_image, dst_image);
+            blit.regions[0].src_offsets = region.src_offsets.map(|[x, y]| [x, y, 0]);
+            blit.regions[0].src_offsets[1][2] = 1;
+            blit.regions[0].dst_offsets = dst_offsets.map(|[x, y]| [x, y, 0]);
+            blit.regions[0].dst_offsets[1][2] = 1;
+            blit.filter = filter;
+            blit
+        };
+
+        let staging_format = match self.srgb_conversion {
+            SrgbConversion::KeepEncoding
+                if is_srgb(source.format()) != is_srgb(target.format()) =>
+            {
+                srgb_counterpart(target.format())
+            }
+            _ => None,
+        };
+
+        let Some(staging_format) = staging_format else {
+            cmd_buf.blit_image(blit(source, target, region.dst_offsets))?;
+            return Ok(());
+        };
+
+        // Blitting between images with the same encoding leaves the values alone, and copying
+        // into the target then reinterprets them without conversion
+        let [[x0, y0], [x1, y1]] = region.dst_offsets;
+        let staging = self.staging.image(
+            graphics_objects,
+            frame_index,
+            staging_format,
+            [x1 - x0, y1 - y0],
+        )?;
+
+        cmd_buf.blit_image(blit(source, staging.clone(), [[0, 0], [x1 - x0, y1 - y0]]))?;
+
+        let mut copy = CopyImageInfo::images(staging, target);
+        copy.regions[0].dst_offset = [x0, y0, 0];
+        cmd_buf.copy_image(copy)?;
+
+        Ok(())
+    }
+}
+
+impl<S> CompositePass<S> {
+    /// The image to composite, `None` while the canvas has no images, e.g. before its first
+    /// [`Canvas::begin_frame`] or when it's `Manual` and was never resized
+    fn source(&self) -> Result<Option<Arc<Image>>, CompositeError> {
+        match self.canvas.resolved_attachment(&self.attachment) {
+            Ok(view) => Ok(Some(view.image().clone())),
+            Err(CanvasError::NotAllocated) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+impl<S: CompositeTarget + FrameContext> RenderPass for CompositePass<S> {
+    type SharedData = S;
+    type PreProcessed = ();
+    type Output = Result<(), CompositeError>;
+    type CmdBufType = Box<CmdBuffer>;
+
+    fn preprocess(
+        &mut self,
+        _: Arc<GraphicsObjects>,
+        _: Arc<Self::SharedData>,
+    ) -> Result<Self::PreProcessed, HaltPolicy> {
+        Ok(())
+    }
+
+    fn build_commands(
+        &mut self,
+        graphics_objects: Arc<GraphicsObjects>,
+        shared: Arc<Self::SharedData>,
+        cmd_buffer: &mut Box<CmdBuffer>,
+        _: Self::PreProcessed,
+    ) -> Result<Self::Output, HaltPolicy> {
+        // Failed commands aren't recorded, so the command buffer can still be submitted
+        Ok(self.record(
+            &graphics_objects,
+            shared.frame_index(),
+            cmd_buffer,
+            shared.composite_target(),
+        ))
+    }
+
+    fn postprocess(
+        &mut self,
+        _: Arc<GraphicsObjects>,
+        _: Arc<Self::SharedData>,
+        output: Self::Output,
+    ) {
+        if let (Err(err), Some(error_handler)) = (output, self.error_handler.as_mut()) {
+            error_handler(err);
+        }
+    }
+}
+
+fn is_srgb(format: Format) -> bool {
+    format.numeric_format_color() == Some(NumericFormat::SRGB)
+}
+
+/// The format with the same layout as `format` but the other encoding, `None` if it has no sRGB
+/// variant
+fn srgb_counterpart(format: Format) -> Option<Format> {
+    const PAIRS: [(Format, Format); 7] = [
+        (Format::R8_UNORM, Format::R8_SRGB),
+        (Format::R8G8_UNORM, Format::R8G8_SRGB),
+        (Format::R8G8B8_UNORM, Format::R8G8B8_SRGB),
+        (Format::B8G8R8_UNORM, Format::B8G8R8_SRGB),
+        (Format::R8G8B8A8_UNORM, Format::R8G8B8A8_SRGB),
+        (Format::B8G8R8A8_UNORM, Format::B8G8R8A8_SRGB),
+        (Format::A8B8G8R8_UNORM_PACK32, Format::A8B8G8R8_SRGB_PACK32),
+    ];
+
+    PAIRS.iter().find_map(|&(unorm, srgb)| {
+        if format == unorm {
+            Some(srgb)
+        } else if format == srgb {
+            Some(unorm)
+        } else {
+            None
+        }
+    })
+}
+
+#[derive(Debug)]
+pub enum CompositeError {
+    Canvas(CanvasError),
+    /// The staging image for [`SrgbConversion::KeepEncoding`] couldn't be created
+    Staging(Validated<AllocateImageError>),
+    Validation(Box<ValidationError>),
+}
+
+impl fmt::Display for CompositeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Canvas(err) => write!(f, "failed to read the canvas: {err}"),
+            Self::Staging(err) => write!(f, "failed to create the staging image: {err}"),
+            Self::Validation(err) => write!(f, "failed to record the composite: {err}"),
+        }
+    }
+}
+
+impl Error for CompositeError {}
+
+impl From<CanvasError> for CompositeError {
+    fn from(err: CanvasError) -> Self {
+        Self::Canvas(err)
+    }
+}
+
+impl From<Validated<AllocateImageError>> for CompositeError {
+    fn from(err: Validated<AllocateImageError>) -> Self {
+        Self::Staging(err)
+    }
+}
+
+impl From<Box<ValidationError>> for CompositeError {
+    fn from(err: Box<ValidationError>) -> Self {
+        Self::Validation(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::canvas::CanvasAttachment;
+
+    #[test]
+    fn unallocated_canvas_has_no_source() {
+        let canvas = Canvas::from_parts(
+            None,
+            vec![CanvasAttachment::new("color", ImageCreateInfo::default())],
+            vec![None],
+        );
+
+        let pass = CompositePass::<()>::new(canvas.clone(), "color");
+        assert!(matches!(pass.source(), Ok(None)));
+
+        let pass = CompositePass::<()>::new(canvas, "normal");
+        assert!(matches!(
+            pass.source(),
+            Err(CompositeError::Canvas(CanvasError::UnknownAttachment(_)))
+        ));
+    }
+
+    #[test]
+    fn stretch_covers_everything() {
+        let region = ScaleMode::Stretch.region([320, 240], [1920, 1080]);
+
+        assert_eq!(region.src_offsets, [[0, 0], [320, 240]]);
+        assert_eq!(region.dst_offsets, [[0, 0], [1920, 1080]]);
+        assert!(!region.has_border([1920, 1080]));
+    }
+
+    #[test]
+    fn letterbox_centers_the_fitted_canvas() {
+        let region = ScaleMode::Letterbox.region([320, 240], [1920, 1080]);
+        assert_eq!(region.src_offsets, [[0, 0], [320, 240]]);
+        assert_eq!(region.dst_offsets, [[240, 0], [1680, 1080]]);
+        assert!(region.has_border([1920, 1080]));
+
+        let region = ScaleMode::Letterbox.region([100, 50], [400, 400]);
+        assert_eq!(region.dst_offsets, [[0, 100], [400, 300]]);
+    }
+
+    #[test]
+    fn integer_scales_by_whole_numbers() {
+        let region = ScaleMode::Integer.region([320, 240], [1920, 1080]);
+
+        assert_eq!(region.src_offsets, [[0, 0], [320, 240]]);
+        assert_eq!(region.dst_offsets, [[320, 60], [1600, 1020]]);
+    }
+
+    #[test]
+    fn integer_falls_back_to_letterbox_when_the_canvas_is_larger() {
+        let src_extent = [800, 600];
+        let dst_extent = [400, 400];
+        let region = ScaleMode::Integer.region(src_extent, dst_extent);
+
+        assert_eq!(region.dst_offsets, [[0, 50], [400, 350]]);
+        assert_eq!(region, ScaleMode::Letterbox.region(src_extent, dst_extent));
+    }
+
+    #[test]
+    fn crop_cuts_off_the_canvas_edges() {
+        let region = ScaleMode::Crop.region([100, 50], [400, 400]);
+        assert_eq!(region.src_offsets, [[25, 0], [75, 50]]);
+        assert_eq!(region.dst_offsets, [[0, 0], [400, 400]]);
+        assert!(!region.has_border([400, 400]));
+
+        let region = ScaleMode::Crop.region([320, 240], [1920, 1080]);
+        assert_eq!(region.src_offsets, [[0, 30], [320, 210]]);
+    }
+
+    #[test]
+    fn zero_extents_use_the_full_images() {
+        for mode in [
+            ScaleMode::Stretch,
+            ScaleMode::Letterbox,
+            ScaleMode::Integer,
+            ScaleMode::Crop,
+        ] {
+            let region = mode.region([0, 240], [1920, 1080]);
+            assert_eq!(region.src_offsets, [[0, 0], [0, 240]]);
+            assert_eq!(region.dst_offsets, [[0, 0], [1920, 1080]]);
+
+            let region = mode.region([320, 240], [1920, 0]);
+            assert_eq!(region.src_offsets, [[0, 0], [320, 240]]);
+            assert_eq!(region.dst_offsets, [[0, 0], [1920, 0]]);
+        }
+    }
+
+    #[test]
+    fn tiny_regions_keep_at_least_one_pixel() {
+        let region = ScaleMode::Letterbox.region([10_000, 10], [100, 100]);
+
+        assert_eq!(region.dst_offsets, [[0, 49], [100, 50]]);
+    }
+}
@@ -1,5 +1,392 @@
-use crate::renderpass::CmdBuffer;
+use std::sync::Arc;
 
+use vulkano::{
+    buffer::{
+        IndexBuffer,
+        Subbuffer,
+    },
+    descriptor_set::PersistentDescriptorSet,
+    pipeline::{
+        GraphicsPipeline,
+        Pipeline,
+        PipelineBindPoint,
+    },
+    Handle,
+    ValidationError,
+    VulkanObject,
+};
+
+use crate::{
+    renderpass::CmdBuffer,
+    GraphicsObjects,
+};
+
+/// What a [`Drawable`] can use to prepare its per-frame data
+pub struct DrawContext<'a> {
+    pub graphics_objects: &'a GraphicsObjects,
+    /// Indexes the per-frame allocators of [`GraphicsObjects`]
+    pub frame_index: usize,
+    /// The pipeline the drawable is drawn with
+    pub pipeline: &'a Arc<GraphicsPipeline>,
+}
+
+/// An object recorded by a [`DrawList`]
 pub trait Drawable {
-    fn draw(&mut self, command_buffer: &mut CmdBuffer);
+    /// Called once per frame before the object is drawn, e.g. to write its uniforms
+    fn prepare(&mut self, _context: &DrawContext) {}
+
+    /// Records the object's draw. Its pipeline and material are already bound, and this may be
+    /// called several times per frame, once for each view
+    fn draw(
+        &mut self,
+        command_buffer: &mut CmdBuffer,
+        pipeline: &Arc<GraphicsPipeline>,
+    ) -> Result<(), Box<ValidationError>>;
+}
+
+/// An indexed mesh, optionally with a descriptor set of its own
+#[derive(Clone)]
+pub struct MeshDraw {
+    pub vertex_buffer: Subbuffer<[u8]>,
+    pub index_buffer: IndexBuffer,
+    pub instance_count: u32,
+    /// The set number and descriptor set of per-object data, such as transforms
+    pub object_set: Option<(u32, Arc<PersistentDescriptorSet>)>,
+}
+
+impl MeshDraw {
+    pub fn new<T: ?Sized>(
+        vertex_buffer: Subbuffer<T>,
+        index_buffer: impl Into<IndexBuffer>,
+    ) -> Self {
+        Self {
+            vertex_buffer: vertex_buffer.into_bytes(),
+            index_buffer: index_buffer.into(),
+            instance_count: 1,
+            object_set: None,
+        }
+    }
+
+    pub fn with_instances(mut self, instance_count: u32) -> Self {
+        self.instance_count = instance_count;
+        self
+    }
+}
+
+impl Drawable for MeshDraw {
+    fn draw(
+        &mut self,
+        command_buffer: &mut CmdBuffer,
+        pipeline: &Arc<GraphicsPipeline>,
+    ) -> Result<(), Box<ValidationError>> {
+        command_buffer
+            .bind_vertex_buffers(0, self.vertex_buffer.clone())?
+            .bind_index_buffer(self.index_buffer.clone())?;
+
+        if let Some((set_number, set)) = &self.object_set {
+            command_buffer.bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                pipeline.layout().clone(),
+                *set_number,
+                set.clone(),
+            )?;
+        }
+
+        command_buffer.draw_indexed(
+            self.index_buffer.len() as u32,
+            self.instance_count,
+            0,
+            0,
+            0,
+        )?;
+        Ok(())
+    }
+}
+
+/// A descriptor set shared by many objects, such as their textures, bound once for all of them
+#[derive(Clone)]
+pub struct Material {
+    pub set_number: u32,
+    pub descriptor_set: Arc<PersistentDescriptorSet>,
+}
+
+impl Material {
+    pub fn new(set_number: u32, descriptor_set: Arc<PersistentDescriptorSet>) -> Self {
+        Self {
+            set_number,
+            descriptor_set,
+        }
+    }
+
+    fn id(&self) -> u64 {
+        self.descriptor_set.handle().as_raw()
+    }
+}
+
+/// The order a [`DrawList`] records its items in. Opaque items come first, grouped by pipeline,
+/// then material, then front to back. Transparent items follow them back to front, since they
+/// have to blend in order
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SortKey {
+    transparent: bool,
+    first: u64,
+    second: u64,
+    third: u64,
+}
+
+impl SortKey {
+    fn new(transparent: bool, pipeline: u64, material: u64, depth: f32) -> Self {
+        let depth = sortable_depth(depth) as u64;
+
+        match transparent {
+            false => Self {
+                transparent: false,
+                first: pipeline,
+                second: material,
+                third: depth,
+            },
+            true => Self {
+                transparent: true,
+                first: !depth,
+                second: pipeline,
+                third: material,
+            },
+        }
+    }
+}
+
+/// An object in a [`DrawList`], along with the state it's drawn with
+pub struct DrawItem {
+    pub pipeline: Arc<GraphicsPipeline>,
+    pub material: Option<Material>,
+    /// View space depth, only used for sorting
+    pub depth: f32,
+    /// Blends with what's behind it, so it's drawn after opaque items
+    pub transparent: bool,
+    pub drawable: Box<dyn Drawable + Send>,
+}
+
+impl DrawItem {
+    pub fn new(pipeline: Arc<GraphicsPipeline>, drawable: impl Drawable + Send + 'static) -> Self {
+        Self {
+            pipeline,
+            material: None,
+            depth: 0.0,
+            transparent: false,
+            drawable: Box::new(drawable),
+        }
+    }
+
+    pub fn with_material(mut self, material: Material) -> Self {
+        self.material = Some(material);
+        self
+    }
+
+    pub fn with_depth(mut self, depth: f32) -> Self {
+        self.depth = depth;
+        self
+    }
+
+    pub fn with_transparency(mut self) -> Self {
+        self.transparent = true;
+        self
+    }
+
+    pub fn sort_key(&self) -> SortKey {
+        SortKey::new(
+            self.transparent,
+            self.pipeline.handle().as_raw(),
+            self.material.as_ref().map_or(0, Material::id),
+            self.depth,
+        )
+    }
+}
+
+/// How much work recording a [`DrawList`] took
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DrawStats {
+    pub draws: usize,
+    pub pipeline_binds: usize,
+    pub material_binds: usize,
+}
+
+/// Collects the objects to draw in a frame, and records them sorted so that consecutive items
+/// share as much state as possible
+#[derive(Default)]
+pub struct DrawList {
+    items: Vec<DrawItem>,
+    sorted: bool,
+}
+
+impl DrawList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, item: DrawItem) -> &mut Self {
+        self.items.push(item);
+        self.sorted = false;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.items.clear();
+    }
+
+    pub fn items(&self) -> &[DrawItem] {
+        &self.items
+    }
+
+    /// Sorts the items by their [`SortKey`], keeping the submission order of equal keys
+    pub fn sort(&mut self) {
+        if !self.sorted {
+            self.items.sort_by_cached_key(DrawItem::sort_key);
+            self.sorted = true;
+        }
+    }
+
+    /// Calls [`Drawable::prepare`] on every item
+    pub fn prepare(&mut self, graphics_objects: &GraphicsObjects, frame_index: usize) {
+        for item in &mut self.items {
+            item.drawable.prepare(&DrawContext {
+                graphics_objects,
+                frame_index,
+                pipeline: &item.pipeline,
+            });
+        }
+    }
+
+    /// Records every item in sorted order, only binding pipelines and materials when they change
+    /// from the previous item's
+    pub fn record(&mut self, cmd_buf: &mut CmdBuffer) -> Result<DrawStats, Box<ValidationError>> {
+        self.sort();
+
+        let mut stats = DrawStats::default();
+        let mut bound_pipeline = None;
+        let mut bound_material = None;
+
+        for item in &mut self.items {
+            let pipeline = item.pipeline.handle().as_raw();
+            if bound_pipeline != Some(pipeline) {
+                cmd_buf.bind_pipeline_graphics(item.pipeline.clone())?;
+                bound_pipeline = Some(pipeline);
+                // Layouts may differ between pipelines, so the material is bound again
+                bound_material = None;
+                stats.pipeline_binds += 1;
+            }
+
+            if let Some(material) = &item.material {
+                if bound_material != Some(material.id()) {
+                    cmd_buf.bind_descriptor_sets(
+                        PipelineBindPoint::Graphics,
+                        item.pipeline.layout().clone(),
+                        material.set_number,
+                        material.descriptor_set.clone(),
+                    )?;
+                    bound_material = Some(material.id());
+                    stats.material_binds += 1;
+                }
+            }
+
+            item.drawable.draw(cmd_buf, &item.pipeline)?;
+            stats.draws += 1;
+        }
+
+        Ok(stats)
+    }
+}
+
+/// Maps a depth to an integer with the same order, negative depths included
+fn sortable_depth(depth: f32) -> u32 {
+    let bits = depth.to_bits();
+    match bits >> 31 {
+        0 => bits | 0x8000_0000,
+        _ => !bits,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sortable_depth_keeps_the_order() {
+        let depths = [
+            f32::NEG_INFINITY,
+            -1e10,
+            -1.5,
+            -1.0,
+            -f32::MIN_POSITIVE,
+            -0.0,
+            0.0,
+            f32::MIN_POSITIVE,
+            1.0,
+            1.5,
+            1e10,
+            f32::INFINITY,
+        ];
+
+        for pair in depths.windows(2) {
+            assert!(
+                sortable_depth(pair[0]) <= sortable_depth(pair[1]),
+                "{} sorts after {}",
+                pair[0],
+                pair[1]
+            );
+        }
+        assert!(sortable_depth(-1.0) < sortable_depth(1.0));
+        assert!(sortable_depth(-0.0) < sortable_depth(0.0));
+    }
+
+    #[test]
+    fn opaque_keys_group_by_pipeline_then_material_then_front_to_back() {
+        let mut keys = [
+            SortKey::new(false, 2, 1, 1.0),
+            SortKey::new(false, 1, 2, 0.5),
+            SortKey::new(false, 1, 1, 3.0),
+            SortKey::new(false, 1, 1, -2.0),
+        ];
+        keys.sort();
+
+        assert_eq!(
+            keys,
+            [
+                SortKey::new(false, 1, 1, -2.0),
+                SortKey::new(false, 1, 1, 3.0),
+                SortKey::new(false, 1, 2, 0.5),
+                SortKey::new(false, 2, 1, 1.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn transparent_keys_follow_opaque_ones_back_to_front() {
+        let mut keys = [
+            SortKey::new(true, 1, 1, 1.0),
+            SortKey::new(false, 9, 9, 100.0),
+            SortKey::new(true, 2, 1, 5.0),
+            SortKey::new(true, 1, 2, -1.0),
+            SortKey::new(true, 2, 1, 1.0),
+        ];
+        keys.sort();
+
+        assert_eq!(
+            keys,
+            [
+                SortKey::new(false, 9, 9, 100.0),
+                SortKey::new(true, 2, 1, 5.0),
+                SortKey::new(true, 1, 1, 1.0),
+                SortKey::new(true, 2, 1, 1.0),
+                SortKey::new(true, 1, 2, -1.0),
+            ]
+        );
+    }
 }
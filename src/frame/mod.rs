@@ -0,0 +1,371 @@
+use std::{
+    ops::{
+        Index,
+        Range,
+    },
+    sync::Arc,
+    thread,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use vulkano::{
+    device::Device,
+    query::{
+        QueryPool,
+        QueryPoolCreateInfo,
+        QueryResultFlags,
+        QueryType,
+    },
+    sync::PipelineStage,
+    ValidationError,
+};
+
+use crate::{
+    canvas::CanvasResizePolicy,
+    renderpass::CmdBuffer,
+};
+
+/// One `T` for each frame-in-flight slot, indexed by the frame index handed out when a frame
+/// is acquired. A slot is only reused once the frame that last used it has finished executing
+#[derive(Clone, Debug)]
+pub struct PerFrame<T> {
+    slots: Vec<T>,
+}
+
+impl<T> PerFrame<T> {
+    pub fn new(num_frames_in_flight: usize, create: impl FnMut(usize) -> T) -> Self {
+        Self {
+            slots: (0..num_frames_in_flight).map(create).collect(),
+        }
+    }
+
+    pub fn get(&self, frame_index: usize) -> &T {
+        &self.slots[frame_index % self.slots.len()]
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.slots.iter()
+    }
+}
+
+impl<T> Index<usize> for PerFrame<T> {
+    type Output = T;
+
+    fn index(&self, frame_index: usize) -> &T {
+        self.get(frame_index)
+    }
+}
+
+/// Shared data of a frame that knows which [`PerFrame`] slot the frame uses, for passes that
+/// need per-frame allocators
+pub trait FrameContext {
+    fn frame_index(&self) -> usize;
+}
+
+/// Frame rate and latency settings of a [`WindowSurface`](crate::window_surface::WindowSurface)
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FramePacing {
+    /// Caps the frame rate by sleeping before a frame is acquired
+    pub target_fps: Option<f64>,
+    /// Waits for the previously submitted frame to finish before starting CPU work on the next,
+    /// trading throughput for input latency
+    pub low_latency: bool,
+}
+
+impl FramePacing {
+    /// Sleeps until a frame started at `last_frame_start` has lasted the target frame time, and
+    /// returns when the next frame is considered to have started
+    pub fn wait(&self, last_frame_start: Option<Instant>) -> Instant {
+        let now = Instant::now();
+        let (Some(target_fps), Some(last_frame_start)) = (self.target_fps, last_frame_start) else {
+            return now;
+        };
+
+        let deadline = last_frame_start + Duration::from_secs_f64(1.0 / target_fps.max(1.0));
+        if now >= deadline {
+            return now;
+        }
+
+        thread::sleep(deadline - now);
+        deadline
+    }
+}
+
+/// Timings of the most recent frame of a window whose execution is known to have finished
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameStats {
+    /// Time between the starts of this frame and the one before it
+    pub frame_interval: Duration,
+    /// CPU time from acquiring the swapchain image to submitting the frame
+    pub cpu_time: Duration,
+    /// GPU execution time between the timestamps written by [`GpuTimer`], if the queue supports
+    /// timestamps and the present path wrote them
+    pub gpu_time: Option<Duration>,
+    /// A coarse upper bound on the time from submitting the frame until its fence signaled, not
+    /// the time the image reached the display. Fences are only checked when a frame is acquired or
+    /// presented, so this can be late by up to one frame interval per frame in flight. Use
+    /// `gpu_time` for the frame's actual execution time
+    pub submit_to_signal_bound: Duration,
+}
+
+/// Adjusts a canvas' render scale so frames stay within a GPU time budget. Feed it the stats
+/// of each finished frame and apply [`DynamicResolution::resize_policy`] to the canvas
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DynamicResolution {
+    /// The GPU time a frame should take
+    pub target_gpu_time: Duration,
+    /// Lowest render scale, relative to the target's extent
+    pub min_scale: f32,
+    /// Highest render scale, above 1 to supersample when there's time to spare
+    pub max_scale: f32,
+    /// How far the scale moves towards its estimate each frame, between 0 and 1
+    pub smoothing: f32,
+    /// Changes smaller than this are ignored, so the scale settles instead of jittering
+    pub threshold: f32,
+    /// The current render scale
+    pub scale: f32,
+}
+
+impl Default for DynamicResolution {
+    fn default() -> Self {
+        Self {
+            target_gpu_time: Duration::from_secs_f64(1.0 / 60.0),
+            min_scale: 0.5,
+            max_scale: 1.0,
+            smoothing: 0.2,
+            threshold: 0.02,
+            scale: 1.0,
+        }
+    }
+}
+
+impl DynamicResolution {
+    /// Moves the scale towards the one estimated to hit the target GPU time and returns it.
+    /// Frames without a GPU time leave the scale unchanged
+    pub fn update(&mut self, stats: &FrameStats) -> f32 {
+        let Some(gpu_time) = stats.gpu_time.filter(|time| !time.is_zero()) else {
+            return self.scale;
+        };
+
+        // GPU time grows roughly with the pixel count, which is the square of the scale
+        let ratio = self.target_gpu_time.as_secs_f32() / gpu_time.as_secs_f32();
+        let estimate = self.scale * ratio.sqrt();
+        let scale = (self.scale + (estimate - self.scale) * self.smoothing.clamp(0.0, 1.0))
+            .clamp(self.min_scale, self.max_scale);
+
+        if (scale - self.scale).abs() >= self.threshold
+            || scale == self.min_scale
+            || scale == self.max_scale
+        {
+            self.scale = scale;
+        }
+
+        self.scale
+    }
+
+    pub fn resize_policy(&self) -> CanvasResizePolicy {
+        CanvasResizePolicy::Scale(self.scale)
+    }
+}
+
+/// Records the GPU execution time of each frame-in-flight slot with timestamp queries
+pub struct GpuTimer {
+    query_pool: Arc<QueryPool>,
+    timestamp_period: f32,
+    written: Vec<bool>,
+}
+
+impl GpuTimer {
+    /// Returns `None` if the queue family doesn't support timestamps
+    pub fn new(
+        device: Arc<Device>,
+        queue_family_index: u32,
+        num_frames_in_flight: usize,
+    ) -> Option<Self> {
+        let physical_device = device.physical_device();
+        physical_device.queue_family_properties()[queue_family_index as usize]
+            .timestamp_valid_bits?;
+        let timestamp_period = physical_device.properties().timestamp_period;
+
+        let query_pool = QueryPool::new(
+            device,
+            QueryPoolCreateInfo {
+                query_count: num_frames_in_flight as u32 * 2,
+                ..QueryPoolCreateInfo::query_type(QueryType::Timestamp)
+            },
+        )
+        .ok()?;
+
+        Some(Self {
+            query_pool,
+            timestamp_period,
+            written: vec![false; num_frames_in_flight],
+        })
+    }
+
+    fn queries(frame_index: usize) -> Range<u32> {
+        frame_index as u32 * 2..frame_index as u32 * 2 + 2
+    }
+
+    /// Records the start timestamp. The previous frame in the same slot must have finished
+    pub fn begin(
+        &mut self,
+        cmd_buf: &mut CmdBuffer,
+        frame_index: usize,
+    ) -> Result<(), Box<ValidationError>> {
+        let queries = Self::queries(frame_index);
+        // Safety: the slot's previous frame has finished, so its queries aren't in use
+        unsafe {
+            cmd_buf
+                .reset_query_pool(self.query_pool.clone(), queries.clone())?
+                .write_timestamp(
+                    self.query_pool.clone(),
+                    queries.start,
+                    PipelineStage::TopOfPipe,
+                )?;
+        }
+        self.written[frame_index] = false;
+        Ok(())
+    }
+
+    /// Records the end timestamp, after every other command of the frame
+    pub fn end(
+        &mut self,
+        cmd_buf: &mut CmdBuffer,
+        frame_index: usize,
+    ) -> Result<(), Box<ValidationError>> {
+        let queries = Self::queries(frame_index);
+        // Safety: the query was reset by `begin` in the same command buffer
+        unsafe {
+            cmd_buf.write_timestamp(
+                self.query_pool.clone(),
+                queries.start + 1,
+                PipelineStage::BottomOfPipe,
+            )?;
+        }
+        self.written[frame_index] = true;
+        Ok(())
+    }
+
+    /// Reads the GPU time of the last frame recorded in the slot, once it has finished executing
+    pub fn read(&mut self, frame_index: usize) -> Option<Duration> {
+        if !std::mem::take(&mut self.written[frame_index]) {
+            return None;
+        }
+
+        let mut timestamps = [0u64; 2];
+        match self.query_pool.get_results(
+            Self::queries(frame_index),
+            &mut timestamps,
+            QueryResultFlags::empty(),
+        ) {
+            Ok(true) => {
+                let ticks = timestamps[1].saturating_sub(timestamps[0]);
+                Some(Duration::from_nanos(
+                    (ticks as f64 * self.timestamp_period as f64) as u64,
+                ))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(gpu_time: Option<Duration>) -> FrameStats {
+        FrameStats {
+            gpu_time,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn dynamic_resolution_ignores_frames_without_gpu_time() {
+        let mut resolution = DynamicResolution {
+            scale: 0.8,
+            ..Default::default()
+        };
+
+        assert_eq!(resolution.update(&stats(None)), 0.8);
+        assert_eq!(resolution.update(&stats(Some(Duration::ZERO))), 0.8);
+    }
+
+    #[test]
+    fn dynamic_resolution_moves_towards_the_target() {
+        let mut resolution = DynamicResolution {
+            target_gpu_time: Duration::from_millis(10),
+            smoothing: 1.0,
+            ..Default::default()
+        };
+
+        // Four times the budget halves the pixel count twice, so the scale halves
+        assert_eq!(
+            resolution.update(&stats(Some(Duration::from_millis(40)))),
+            0.5
+        );
+
+        resolution.max_scale = 2.0;
+        assert_eq!(
+            resolution.update(&stats(Some(Duration::from_millis(10)))),
+            0.5
+        );
+        assert_eq!(
+            resolution.update(&stats(Some(Duration::from_micros(2500)))),
+            1.0
+        );
+    }
+
+    #[test]
+    fn dynamic_resolution_clamps_and_snaps_to_its_limits() {
+        let mut resolution = DynamicResolution {
+            target_gpu_time: Duration::from_millis(10),
+            smoothing: 1.0,
+            ..Default::default()
+        };
+
+        assert_eq!(resolution.update(&stats(Some(Duration::from_secs(1)))), 0.5);
+        assert_eq!(
+            resolution.update(&stats(Some(Duration::from_micros(1)))),
+            1.0
+        );
+
+        // Changes within the threshold are ignored, except when they reach a limit
+        resolution.scale = 0.99;
+        resolution.threshold = 0.05;
+        assert_eq!(
+            resolution.update(&stats(Some(Duration::from_micros(9900)))),
+            0.99
+        );
+        assert_eq!(
+            resolution.update(&stats(Some(Duration::from_millis(9)))),
+            1.0
+        );
+    }
+
+    #[test]
+    fn dynamic_resolution_smooths_changes() {
+        let mut resolution = DynamicResolution {
+            target_gpu_time: Duration::from_millis(10),
+            smoothing: 0.5,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            resolution.update(&stats(Some(Duration::from_millis(40)))),
+            0.75
+        );
+    }
+}
@@ -1,12 +1,19 @@
 pub mod canvas;
+pub mod composite;
 pub mod drawable;
+pub mod frame;
+pub mod memory;
 pub mod render_system;
 pub mod renderpass;
+pub mod scene;
 pub mod submit_system;
+pub mod view;
 pub mod window_surface;
 
+#[cfg(feature = "winit")]
+use std::collections::HashMap;
 use std::{
-    collections::HashMap,
+    any::Any,
     sync::{
         mpsc::{
             channel,
@@ -20,8 +27,24 @@ use std::{
     thread,
 };
 
+use canvas::Canvas;
+#[cfg(feature = "winit")]
+use canvas::CanvasRegistry;
+use frame::PerFrame;
+use memory::MemoryReport;
 use parking_lot::Mutex;
+use raw_window_handle::{
+    HasRawDisplayHandle,
+    HasRawWindowHandle,
+};
 use vulkano::{
+    buffer::{
+        allocator::{
+            SubbufferAllocator,
+            SubbufferAllocatorCreateInfo,
+        },
+        BufferUsage,
+    },
     command_buffer::allocator::StandardCommandBufferAllocator,
     descriptor_set::allocator::StandardDescriptorSetAllocator,
     device::{
@@ -34,35 +57,41 @@ use vulkano::{
         QueueCreateInfo,
         QueueFlags,
     },
-    image::{
-        view::ImageView,
-        Image,
-        ImageUsage,
-    },
     instance::{
         Instance,
         InstanceCreateFlags,
         InstanceCreateInfo,
+        InstanceExtensions,
     },
-    memory::allocator::StandardMemoryAllocator,
-    pipeline::graphics::viewport::Viewport,
-    render_pass::{
-        Framebuffer,
-        FramebufferCreateInfo,
-        RenderPass,
-    },
-    swapchain::{
-        Surface,
-        Swapchain,
-        SwapchainCreateInfo,
+    memory::allocator::{
+        MemoryTypeFilter,
+        StandardMemoryAllocator,
     },
+    swapchain::Surface,
+    Version,
     VulkanLibrary,
 };
-use window_surface::WindowSurface;
+#[cfg(feature = "winit")]
+use vulkano::{
+    Validated,
+    VulkanError,
+};
+use window_surface::{
+    SurfaceConfig,
+    SurfaceTarget,
+    WindowSurface,
+    WindowSurfaceError,
+};
+#[cfg(feature = "winit")]
 use winit::{
     dpi::PhysicalSize,
-    event_loop::EventLoop,
+    event_loop::{
+        ControlFlow,
+        EventLoop,
+        EventLoopWindowTarget,
+    },
     window::{
+        Fullscreen,
         WindowBuilder,
         WindowId,
     },
@@ -78,39 +107,131 @@ pub struct GraphicsObjects {
     pub descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
     pub command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
     pub memory_allocator: Arc<StandardMemoryAllocator>,
+    /// Command buffer allocators for per-frame work, indexed by frame index
+    pub frame_command_buffer_allocators: PerFrame<Arc<StandardCommandBufferAllocator>>,
+    /// Host-writable uniform buffer allocators, indexed by frame index
+    pub frame_uniform_allocators: PerFrame<Arc<Mutex<SubbufferAllocator>>>,
+}
+
+/// Options used by [`Renderer::with_config`]
+#[derive(Clone, Debug)]
+pub struct RendererConfig {
+    /// Swapchain options for the primary window
+    pub surface: SurfaceConfig,
+    /// How many frames the CPU may record ahead of the GPU, independent of how many images the
+    /// swapchains have
+    pub frames_in_flight: usize,
+}
+
+impl Default for RendererConfig {
+    fn default() -> Self {
+        Self {
+            surface: SurfaceConfig::default(),
+            frames_in_flight: 2,
+        }
+    }
 }
 
 pub struct Renderer {
     pub comms: RenderThreadComms,
+    #[cfg(feature = "winit")]
     pub windows: HashMap<WindowId, Arc<Mutex<WindowSurface>>>,
+    /// Canvases instantiated per window, dropped along with their window
+    #[cfg(feature = "winit")]
+    pub canvases: CanvasRegistry<WindowId>,
     pub graphics_objects: GraphicsObjects,
 }
 
 impl Renderer {
+    #[cfg(feature = "winit")]
     pub fn new<ELT>(event_loop: &EventLoop<ELT>) -> (Self, WindowId) {
-        let library = VulkanLibrary::new().unwrap();
-        let required_extensions = Surface::required_extensions(&event_loop);
-
-        let instance = Instance::new(
-            library,
-            InstanceCreateInfo {
-                flags: InstanceCreateFlags::ENUMERATE_PORTABILITY,
-                enabled_extensions: required_extensions,
-                ..Default::default()
-            },
-        )
-        .unwrap();
+        Self::with_config(event_loop, RendererConfig::default())
+    }
+
+    #[cfg(feature = "winit")]
+    pub fn with_config<ELT>(
+        event_loop: &EventLoop<ELT>,
+        config: RendererConfig,
+    ) -> (Self, WindowId) {
+        let instance = create_instance(event_loop);
 
         let window = Arc::new(
             WindowBuilder::new()
                 .with_title("Primary window")
                 .with_inner_size(PhysicalSize::new(400, 400))
-                .build(&event_loop)
+                .with_transparent(config.surface.transparent())
+                .build(event_loop)
                 .unwrap(),
         );
 
         let surface = Surface::from_window(instance.clone(), window.clone()).unwrap();
 
+        let mut renderer = Self::from_surface(&instance, &surface, config.frames_in_flight);
+
+        let window_id = window.id();
+        let window_surface = WindowSurface::from_surface(
+            SurfaceTarget::Winit(window),
+            surface,
+            &renderer.graphics_objects,
+            config.surface,
+        )
+        .expect("failed to create primary window surface");
+
+        renderer
+            .windows
+            .insert(window_id, Arc::new(Mutex::new(window_surface)));
+
+        (renderer, window_id)
+    }
+
+    /// Creates a renderer presenting to a window owned by something other than winit, such as a
+    /// viewport embedded in another UI toolkit. The caller keeps the returned surface's extent up
+    /// to date with [`WindowSurface::set_extent`]. The renderer doesn't track raw handle surfaces,
+    /// so they get no canvas registry instances: create their canvases directly, and pass them to
+    /// [`Renderer::canvas_memory_report`] to have them counted
+    pub fn from_raw_handle<W>(
+        handle: Arc<W>,
+        extent: [u32; 2],
+        config: RendererConfig,
+    ) -> Result<(Self, Arc<Mutex<WindowSurface>>), WindowSurfaceError>
+    where
+        W: HasRawWindowHandle + HasRawDisplayHandle + Any + Send + Sync,
+    {
+        let instance = create_instance(handle.as_ref());
+        let surface = Surface::from_window(instance.clone(), handle)?;
+
+        let renderer = Self::from_surface(&instance, &surface, config.frames_in_flight);
+
+        let window_surface = WindowSurface::from_surface(
+            SurfaceTarget::Raw { extent },
+            surface,
+            &renderer.graphics_objects,
+            config.surface,
+        )?;
+
+        Ok((renderer, Arc::new(Mutex::new(window_surface))))
+    }
+
+    /// Creates another surface from raw handles, presenting from the renderer's device and
+    /// graphics queue. Unlike winit windows, these aren't tracked by the renderer, see
+    /// [`Renderer::from_raw_handle`]
+    pub fn create_raw_surface<W>(
+        &self,
+        handle: Arc<W>,
+        extent: [u32; 2],
+        config: SurfaceConfig,
+    ) -> Result<Arc<Mutex<WindowSurface>>, WindowSurfaceError>
+    where
+        W: HasRawWindowHandle + HasRawDisplayHandle + Any + Send + Sync,
+    {
+        let window_surface =
+            WindowSurface::from_raw_handle(handle, extent, &self.graphics_objects, config)?;
+
+        Ok(Arc::new(Mutex::new(window_surface)))
+    }
+
+    /// Picks a device able to present to `surface` and spawns the render thread
+    fn from_surface(instance: &Arc<Instance>, surface: &Surface, frames_in_flight: usize) -> Self {
         let device_extensions = DeviceExtensions {
             khr_swapchain: true,
             ..DeviceExtensions::empty()
@@ -126,7 +247,7 @@ impl Renderer {
                     .enumerate()
                     .position(|(i, q)| {
                         q.queue_flags.intersects(QueueFlags::GRAPHICS)
-                            && p.surface_support(i as u32, &surface).unwrap_or(false)
+                            && p.surface_support(i as u32, surface).unwrap_or(false)
                     })
                     .map(|i| (p, i as u32))
             })
@@ -149,12 +270,21 @@ impl Renderer {
             physical_device.properties().max_vertex_input_bindings,
         );
 
+        // Dynamic rendering is optional, canvases created without a render pass need it
+        let dynamic_rendering = physical_device.supported_features().dynamic_rendering;
+        let device_extensions = DeviceExtensions {
+            khr_dynamic_rendering: dynamic_rendering
+                && physical_device.api_version() < Version::V1_3,
+            ..device_extensions
+        };
+
         let (device, mut queues) = Device::new(
             physical_device,
             DeviceCreateInfo {
                 enabled_extensions: device_extensions,
                 enabled_features: Features {
                     fill_mode_non_solid: true,
+                    dynamic_rendering,
                     ..Default::default()
                 },
                 queue_create_infos: vec![QueueCreateInfo {
@@ -169,39 +299,7 @@ impl Renderer {
 
         let queue = queues.next().unwrap();
 
-        let surface_capabilities = device
-            .physical_device()
-            .surface_capabilities(&surface, Default::default())
-            .unwrap();
-
-        let num_frames_in_flight = surface_capabilities.min_image_count.max(2);
-
-        let surface_image_format = device
-            .physical_device()
-            .surface_formats(&surface, Default::default())
-            .unwrap()[0]
-            .0;
-
-        let (swapchain, images) = {
-            Swapchain::new(
-                device.clone(),
-                surface,
-                SwapchainCreateInfo {
-                    min_image_count: num_frames_in_flight,
-                    image_format: surface_image_format,
-                    image_extent: window.inner_size().into(),
-                    image_usage: ImageUsage::COLOR_ATTACHMENT | ImageUsage::TRANSFER_DST,
-                    composite_alpha: surface_capabilities
-                        .supported_composite_alpha
-                        .into_iter()
-                        .next()
-                        .unwrap(),
-                    present_mode: vulkano::swapchain::PresentMode::Fifo,
-                    ..Default::default()
-                },
-            )
-            .unwrap()
-        };
+        let num_frames_in_flight = frames_in_flight.max(1);
 
         let memory_allocator = Arc::new(StandardMemoryAllocator::new_default(device.clone()));
 
@@ -210,47 +308,44 @@ impl Renderer {
             Default::default(),
         ));
 
-        //let framebuffers = window_size_dependent_setup(&images, renderpass.clone(), &mut viewport);
-        let previous_frame_fences = (0..images.len()).map(|_| None).collect::<Vec<_>>();
-
         let command_buffer_allocator = Arc::new(StandardCommandBufferAllocator::new(
             device.clone(),
             Default::default(),
         ));
 
-        let mut windows = HashMap::new();
-        let window_id = window.id();
-        windows.insert(
-            window_id,
-            Arc::new(Mutex::new(WindowSurface {
-                window,
-                swapchain,
-                images,
-                framebuffers: Vec::new(),
-                //viewport,
-                recreate_swapchain: true,
-                previous_frame_fences,
-                num_frames_in_flight: 0,
-                previous_frame_index: 0,
-                surface_image_format,
-            })),
-        );
-
-        //let window_2 = WindowSurface::new(event_loop, device.clone());
-        //windows.insert(window_2.window.id(), Arc::new(Mutex::new(window_2)));
+        let frame_command_buffer_allocators = PerFrame::new(num_frames_in_flight, |_| {
+            Arc::new(StandardCommandBufferAllocator::new(
+                device.clone(),
+                Default::default(),
+            ))
+        });
+
+        let frame_uniform_allocators = PerFrame::new(num_frames_in_flight, |_| {
+            Arc::new(Mutex::new(SubbufferAllocator::new(
+                memory_allocator.clone(),
+                SubbufferAllocatorCreateInfo {
+                    buffer_usage: BufferUsage::UNIFORM_BUFFER,
+                    memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                        | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                    ..Default::default()
+                },
+            )))
+        });
 
         let graphics_objects_original = GraphicsObjects {
-            num_frames_in_flight: num_frames_in_flight as usize,
+            num_frames_in_flight,
             device: device.clone(),
             graphics_queue: queue.clone(),
             descriptor_set_allocator: descriptor_set_allocator.clone(),
             command_buffer_allocator: command_buffer_allocator.clone(),
             memory_allocator: memory_allocator.clone(),
+            frame_command_buffer_allocators,
+            frame_uniform_allocators,
         };
 
         let graphics_objects = graphics_objects_original.clone();
 
-        let (sender, reciever) = sync_channel::<(Box<dyn RenderSystem + Send>, Sender<()>)>(1);
+        let (sender, reciever) = sync_channel::<RenderThreadMessage>(1);
         let render_closure = move || {
             let graphics_objects = Arc::new(graphics_objects_original.clone());
             loop {
@@ -275,14 +370,120 @@ impl Renderer {
             render_thread: Some(render_thread),
         };
 
-        (
-            Self {
-                comms,
-                windows,
-                graphics_objects,
-            },
-            window_id,
-        )
+        Self {
+            comms,
+            #[cfg(feature = "winit")]
+            windows: HashMap::new(),
+            #[cfg(feature = "winit")]
+            canvases: CanvasRegistry::new(),
+            graphics_objects,
+        }
+    }
+
+    /// Builds a new window presenting from the renderer's device and graphics queue
+    #[cfg(feature = "winit")]
+    pub fn create_window<ELT>(
+        &mut self,
+        event_loop: &EventLoopWindowTarget<ELT>,
+        window_builder: WindowBuilder,
+        config: SurfaceConfig,
+    ) -> Result<WindowId, WindowSurfaceError> {
+        let window_surface =
+            WindowSurface::new(event_loop, window_builder, &self.graphics_objects, config)?;
+
+        let window_id = window_surface.window().unwrap().id();
+        self.windows
+            .insert(window_id, Arc::new(Mutex::new(window_surface)));
+
+        Ok(window_id)
+    }
+
+    /// Removes a window, waiting for its in-flight frames to finish before the swapchain and
+    /// window are dropped. Returns false if the window doesn't belong to the renderer. The window
+    /// is removed even if waiting fails
+    #[cfg(feature = "winit")]
+    pub fn destroy_window(&mut self, window_id: WindowId) -> Result<bool, Validated<VulkanError>> {
+        match self.windows.remove(&window_id) {
+            None => Ok(false),
+            Some(window) => {
+                self.canvases.remove_key(&window_id);
+                window.lock().wait_for_frames()?;
+                Ok(true)
+            }
+        }
+    }
+
+    /// The window's instance of the canvas registered as `name` in [`Renderer::canvases`],
+    /// `None` if the window doesn't belong to the renderer or there's no such canvas
+    #[cfg(feature = "winit")]
+    pub fn window_canvas(&mut self, window_id: WindowId, name: &str) -> Option<Arc<Canvas>> {
+        if !self.windows.contains_key(&window_id) {
+            return None;
+        }
+
+        self.canvases.get(window_id, name)
+    }
+
+    /// Memory used by the images of `canvases`, such as those rendered to raw handle surfaces, and
+    /// of the canvases in [`Renderer::canvases`] that hold images. Only canvases are counted, not
+    /// the renderer's per-frame uniform arenas, whose memory vulkano doesn't expose. Buffers the
+    /// application allocated can be added with [`MemoryReport::add_buffer`]
+    pub fn canvas_memory_report<'a>(
+        &self,
+        canvases: impl IntoIterator<Item = (&'a str, &'a Arc<Canvas>)>,
+    ) -> MemoryReport {
+        let mut report = MemoryReport::new(&self.graphics_objects.device);
+        for (name, canvas) in canvases {
+            report.add_canvas(name, canvas);
+        }
+
+        #[cfg(feature = "winit")]
+        for (name, window_id, canvas) in self.canvases.iter() {
+            if canvas.memory_usage().images == 0 {
+                continue;
+            }
+
+            match window_id {
+                None => report.add_canvas(name, canvas),
+                Some(window_id) => report.add_canvas(format!("{name} {window_id:?}"), canvas),
+            };
+        }
+
+        report
+    }
+
+    /// Switches a window between windowed, borderless and exclusive fullscreen, returns false if
+    /// the window doesn't belong to the renderer. The lock on the window makes sure the switch
+    /// doesn't happen while a frame for it is being acquired or presented
+    #[cfg(feature = "winit")]
+    pub fn set_fullscreen(&self, window_id: WindowId, fullscreen: Option<Fullscreen>) -> bool {
+        match self.windows.get(&window_id) {
+            None => false,
+            Some(window) => {
+                window.lock().set_fullscreen(fullscreen);
+                true
+            }
+        }
+    }
+
+    /// The windows a rendered frame would currently be visible in
+    #[cfg(feature = "winit")]
+    pub fn presentable_windows(
+        &self,
+    ) -> impl Iterator<Item = (&WindowId, &Arc<Mutex<WindowSurface>>)> {
+        self.windows
+            .iter()
+            .filter(|(_, window)| window.lock().is_presentable())
+    }
+
+    /// `Poll` while any window can be presented to, otherwise `Wait`, so the event loop sleeps
+    /// instead of spinning until a window is restored or uncovered
+    #[cfg(feature = "winit")]
+    pub fn control_flow(&self) -> ControlFlow {
+        match self.presentable_windows().next() {
+            Some(_) => ControlFlow::Poll,
+            None => ControlFlow::Wait,
+        }
     }
 
     pub fn device(&self) -> &Arc<Device> {
@@ -294,11 +495,33 @@ impl Renderer {
     }
 }
 
+type RenderThreadMessage = (Box<dyn RenderSystem + Send>, Sender<()>);
+
 pub struct RenderThreadComms {
-    pub sender: Option<SyncSender<(Box<dyn RenderSystem + Send>, Sender<()>)>>,
+    pub sender: Option<SyncSender<RenderThreadMessage>>,
     pub render_thread: Option<thread::JoinHandle<()>>,
 }
 
+/// Creates an instance with the extensions needed to present to `display`
+fn create_instance(display: &impl HasRawDisplayHandle) -> Arc<Instance> {
+    let library = VulkanLibrary::new().unwrap();
+    let required_extensions = InstanceExtensions {
+        // Needed for the HDR color spaces to be reported by the surface
+        ext_swapchain_colorspace: library.supported_extensions().ext_swapchain_colorspace,
+        ..Surface::required_extensions(display)
+    };
+
+    Instance::new(
+        library,
+        InstanceCreateInfo {
+            flags: InstanceCreateFlags::ENUMERATE_PORTABILITY,
+            enabled_extensions: required_extensions,
+            ..Default::default()
+        },
+    )
+    .unwrap()
+}
+
 impl RenderThreadComms {
     pub fn send(&mut self, render_system: impl RenderSystem + Send + 'static) -> PresentBarrier {
         let (sender, reciever) = channel();
@@ -338,27 +561,3 @@ impl Drop for PresentBarrier {
         self.blocking_wait()
     }
 }
-
-fn window_size_dependent_setup(
-    images: &[Arc<Image>],
-    render_pass: Arc<RenderPass>,
-    viewport: &mut Viewport,
-) -> Vec<Arc<Framebuffer>> {
-    let extent = images[0].extent();
-    viewport.extent = [extent[0] as f32, extent[1] as f32];
-
-    images
-        .iter()
-        .map(|image| {
-            let view = ImageView::new_default(image.clone()).unwrap();
-            Framebuffer::new(
-                render_pass.clone(),
-                FramebufferCreateInfo {
-                    attachments: vec![view],
-                    ..Default::default()
-                },
-            )
-            .unwrap()
-        })
-        .collect::<Vec<_>>()
-}
\ No newline at end of file
@@ -0,0 +1,175 @@
+use std::{
+    fmt,
+    sync::Arc,
+};
+
+use vulkano::{
+    buffer::{
+        Buffer,
+        BufferMemory,
+    },
+    device::{
+        Device,
+        DeviceOwned,
+    },
+    memory::MemoryHeapFlags,
+    DeviceSize,
+};
+
+use crate::canvas::{
+    Canvas,
+    CanvasMemoryUsage,
+};
+
+/// One of the device's memory heaps, and how much of it the reported resources use
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HeapUsage {
+    pub size: DeviceSize,
+    /// Whether the heap is video memory
+    pub device_local: bool,
+    pub used: DeviceSize,
+}
+
+/// Memory used by canvases and buffers, grouped by the device's memory heaps. Only the
+/// resources added to the report are counted, allocations made elsewhere (such as the per-frame
+/// uniform arenas) aren't
+#[derive(Clone, Debug, Default)]
+pub struct MemoryReport {
+    /// Indexed by heap index
+    pub heaps: Vec<HeapUsage>,
+    pub canvases: Vec<(String, CanvasMemoryUsage)>,
+    pub buffers: Vec<(String, DeviceSize)>,
+}
+
+impl MemoryReport {
+    /// An empty report for the heaps of `device`
+    pub fn new(device: &Device) -> Self {
+        let heaps = device
+            .physical_device()
+            .memory_properties()
+            .memory_heaps
+            .iter()
+            .map(|heap| HeapUsage {
+                size: heap.size,
+                device_local: heap.flags.intersects(MemoryHeapFlags::DEVICE_LOCAL),
+                used: 0,
+            })
+            .collect();
+
+        Self {
+            heaps,
+            ..Default::default()
+        }
+    }
+
+    pub fn add_canvas(&mut self, name: impl Into<String>, canvas: &Arc<Canvas>) -> &mut Self {
+        let usage = canvas.memory_usage();
+        for (heap, bytes) in self.heaps.iter_mut().zip(&usage.heaps) {
+            heap.used += bytes;
+        }
+
+        self.canvases.push((name.into(), usage));
+        self
+    }
+
+    /// Adds the whole of `buffer`, even when only part of it is in use, e.g. through
+    /// [`Subbuffer::buffer`](vulkano::buffer::Subbuffer::buffer)
+    pub fn add_buffer(&mut self, name: impl Into<String>, buffer: &Buffer) -> &mut Self {
+        let bytes = buffer.memory_requirements().layout.size();
+        if let BufferMemory::Normal(memory) = buffer.memory() {
+            let memory_type_index = memory.device_memory().memory_type_index() as usize;
+            let heap_index = buffer
+                .device()
+                .physical_device()
+                .memory_properties()
+                .memory_types[memory_type_index]
+                .heap_index;
+
+            if let Some(heap) = self.heaps.get_mut(heap_index as usize) {
+                heap.used += bytes;
+            }
+        }
+
+        self.buffers.push((name.into(), bytes));
+        self
+    }
+
+    /// The bytes used by everything in the report
+    pub fn total(&self) -> DeviceSize {
+        self.heaps.iter().map(|heap| heap.used).sum()
+    }
+
+    /// The bytes used in device local heaps
+    pub fn device_local(&self) -> DeviceSize {
+        self.heaps
+            .iter()
+            .filter(|heap| heap.device_local)
+            .map(|heap| heap.used)
+            .sum()
+    }
+}
+
+impl fmt::Display for MemoryReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "total: {} ({} device local)",
+            Bytes(self.total()),
+            Bytes(self.device_local())
+        )?;
+
+        for (index, heap) in self.heaps.iter().enumerate() {
+            let kind = match heap.device_local {
+                true => "device local",
+                false => "host",
+            };
+            writeln!(
+                f,
+                "heap {index} ({kind}): {} of {}",
+                Bytes(heap.used),
+                Bytes(heap.size)
+            )?;
+        }
+
+        for (name, usage) in &self.canvases {
+            writeln!(
+                f,
+                "canvas \"{name}\": {} in {} images, {} lazy",
+                Bytes(usage.bytes()),
+                usage.images,
+                Bytes(usage.lazy_bytes)
+            )?;
+            for (attachment, bytes) in &usage.attachments {
+                writeln!(f, "  {attachment}: {}", Bytes(*bytes))?;
+            }
+        }
+
+        for (name, bytes) in &self.buffers {
+            writeln!(f, "buffer \"{name}\": {}", Bytes(*bytes))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Formats a byte count in binary units
+struct Bytes(DeviceSize);
+
+impl fmt::Display for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+        if self.0 < 1024 {
+            return write!(f, "{} B", self.0);
+        }
+
+        let mut value = self.0 as f64 / 1024.0;
+        let mut unit = 0;
+        while value >= 1024.0 && unit < UNITS.len() - 1 {
+            value /= 1024.0;
+            unit += 1;
+        }
+
+        write!(f, "{value:.1} {}", UNITS[unit])
+    }
+}
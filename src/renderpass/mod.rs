@@ -1,9 +1,20 @@
 use std::sync::Arc;
 
-use vulkano::command_buffer::{
-    allocator::StandardCommandBufferAllocator,
-    AutoCommandBufferBuilder,
-    PrimaryAutoCommandBuffer,
+use vulkano::{
+    command_buffer::{
+        allocator::StandardCommandBufferAllocator,
+        AutoCommandBufferBuilder,
+        PrimaryAutoCommandBuffer,
+    },
+    pipeline::{
+        graphics::viewport::{
+            Scissor,
+            Viewport,
+        },
+        DynamicState,
+        GraphicsPipeline,
+    },
+    ValidationError,
 };
 
 use crate::GraphicsObjects;
@@ -13,6 +24,33 @@ pub type CmdBuffer = AutoCommandBufferBuilder<
     Arc<StandardCommandBufferAllocator>,
 >;
 
+/// Sets viewport and scissor 0, leaving out whichever of the two `bound_pipeline` has as fixed
+/// state, since setting those fails validation. Without a bound pipeline both are set
+pub fn set_viewport_and_scissor<'a>(
+    cmd_buf: &'a mut CmdBuffer,
+    viewport: Viewport,
+    scissor: Scissor,
+    bound_pipeline: Option<&GraphicsPipeline>,
+) -> Result<&'a mut CmdBuffer, Box<ValidationError>> {
+    let dynamic = |state| match bound_pipeline {
+        None => true,
+        Some(pipeline) => pipeline.dynamic_state().contains(&state),
+    };
+
+    if dynamic(DynamicState::Viewport) {
+        cmd_buf.set_viewport(0, [viewport].into_iter().collect())?;
+    }
+    if dynamic(DynamicState::Scissor) {
+        cmd_buf.set_scissor(0, [scissor].into_iter().collect())?;
+    }
+
+    Ok(cmd_buf)
+}
+
+/// Receives the errors a pass recovered from by skipping its work, leaving the frame to be
+/// submitted without it
+pub type ErrorHandler<E> = Box<dyn FnMut(E) + Send>;
+
 pub enum HaltPolicy {
     HaltThis,
     HaltAll,
@@ -0,0 +1,300 @@
+use std::{
+    error::Error,
+    fmt,
+    marker::PhantomData,
+    sync::Arc,
+};
+
+use vulkano::{
+    descriptor_set::{
+        PersistentDescriptorSet,
+        WriteDescriptorSet,
+    },
+    format::ClearValue,
+    memory::allocator::MemoryAllocatorError,
+    pipeline::{
+        Pipeline,
+        PipelineBindPoint,
+    },
+    Validated,
+    ValidationError,
+    VulkanError,
+};
+
+use crate::{
+    canvas::{
+        Canvas,
+        CanvasError,
+        PassError,
+        RenderPassController,
+    },
+    drawable::{
+        DrawList,
+        DrawStats,
+    },
+    frame::FrameContext,
+    renderpass::{
+        CmdBuffer,
+        ErrorHandler,
+        HaltPolicy,
+        RenderPass,
+    },
+    view::{
+        FrameView,
+        ViewLayout,
+    },
+    GraphicsObjects,
+};
+
+/// Draws a [`DrawList`] into a canvas' render pass once for every view of a [`ViewLayout`],
+/// binding each view's camera uniforms to binding 0 of set `camera_set`. The pipelines need
+/// dynamic viewport and scissor unless every view covers the whole canvas
+pub struct ScenePass<S> {
+    pub canvas: Arc<Canvas>,
+    pub views: ViewLayout,
+    pub draw_list: DrawList,
+    /// The set number of the camera uniforms in the pipelines' layouts, which have to agree on
+    /// every set up to it
+    pub camera_set: u32,
+    /// Clear values overriding the canvas' for this pass, by attachment name
+    pub clear_values: Vec<(String, ClearValue)>,
+    error_handler: Option<ErrorHandler<SceneError>>,
+    shared: PhantomData<fn(&S)>,
+}
+
+impl<S> ScenePass<S> {
+    pub fn new(canvas: Arc<Canvas>, views: ViewLayout, draw_list: DrawList) -> Self {
+        Self {
+            canvas,
+            views,
+            draw_list,
+            camera_set: 0,
+            clear_values: Vec::new(),
+            error_handler: None,
+            shared: PhantomData,
+        }
+    }
+
+    pub fn with_camera_set(mut self, camera_set: u32) -> Self {
+        self.camera_set = camera_set;
+        self
+    }
+
+    pub fn with_clear_value(
+        mut self,
+        attachment: impl Into<String>,
+        clear_value: impl Into<ClearValue>,
+    ) -> Self {
+        self.clear_values
+            .push((attachment.into(), clear_value.into()));
+        self
+    }
+
+    /// Handles the errors of the pass when it runs in a render system. The frame is still
+    /// submitted after an error, since its image has been acquired, and without a handler the
+    /// error is dropped
+    pub fn with_error_handler(
+        mut self,
+        error_handler: impl FnMut(SceneError) + Send + 'static,
+    ) -> Self {
+        self.error_handler = Some(Box::new(error_handler));
+        self
+    }
+}
+
+impl<S> ScenePass<S> {
+    /// Records the render pass, drawing the list once for each view
+    pub fn record(
+        &mut self,
+        graphics_objects: &GraphicsObjects,
+        frame_index: usize,
+        cmd_buf: &mut CmdBuffer,
+    ) -> Result<DrawStats, SceneError> {
+        let frame_views = {
+            let uniforms = graphics_objects.frame_uniform_allocators[frame_index].lock();
+            self.views
+                .frame_views([0, 0], self.canvas.extent(), &uniforms)?
+        };
+
+        let mut pass_controller = self.canvas.pass_controller()?;
+        for (attachment, clear_value) in &self.clear_values {
+            pass_controller.set_clear_value(attachment, *clear_value)?;
+        }
+        pass_controller.begin_renderpass(cmd_buf)?;
+
+        // The render pass is ended even if a view fails, so the command buffer can still be built
+        let stats = self.record_views(
+            graphics_objects,
+            &frame_views,
+            &mut pass_controller,
+            cmd_buf,
+        );
+        pass_controller.end_renderpass(cmd_buf)?;
+
+        stats
+    }
+
+    fn record_views(
+        &mut self,
+        graphics_objects: &GraphicsObjects,
+        frame_views: &[FrameView],
+        pass_controller: &mut RenderPassController,
+        cmd_buf: &mut CmdBuffer,
+    ) -> Result<DrawStats, SceneError> {
+        // Every pipeline agrees on the camera set, so any of them can bind it
+        let layout = self
+            .draw_list
+            .items()
+            .first()
+            .map(|item| item.pipeline.layout().clone());
+        let camera_set_layout = match &layout {
+            None => None,
+            Some(layout) => Some(
+                layout
+                    .set_layouts()
+                    .get(self.camera_set as usize)
+                    .ok_or(SceneError::MissingCameraSet(self.camera_set))?
+                    .clone(),
+            ),
+        };
+
+        let mut stats = DrawStats::default();
+        for view in frame_views {
+            pass_controller.begin_view(cmd_buf, view)?;
+
+            let (Some(layout), Some(camera_set_layout)) = (&layout, &camera_set_layout) else {
+                continue;
+            };
+
+            let camera_set = PersistentDescriptorSet::new(
+                &graphics_objects.descriptor_set_allocator,
+                camera_set_layout.clone(),
+                [WriteDescriptorSet::buffer(0, view.camera.clone())],
+                [],
+            )?;
+
+            cmd_buf.bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                layout.clone(),
+                self.camera_set,
+                camera_set,
+            )?;
+
+            let view_stats = self.draw_list.record(cmd_buf)?;
+            stats.draws += view_stats.draws;
+            stats.pipeline_binds += view_stats.pipeline_binds;
+            stats.material_binds += view_stats.material_binds;
+
+            // The next view's viewport and scissor are set with the last item's pipeline bound
+            let bound_pipeline = self
+                .draw_list
+                .items()
+                .last()
+                .map(|item| item.pipeline.clone());
+            pass_controller.set_bound_pipeline(bound_pipeline);
+        }
+
+        Ok(stats)
+    }
+}
+
+impl<S: FrameContext> RenderPass for ScenePass<S> {
+    type SharedData = S;
+    type PreProcessed = ();
+    type Output = Result<DrawStats, SceneError>;
+    type CmdBufType = Box<CmdBuffer>;
+
+    fn preprocess(
+        &mut self,
+        graphics_objects: Arc<GraphicsObjects>,
+        shared: Arc<Self::SharedData>,
+    ) -> Result<Self::PreProcessed, HaltPolicy> {
+        self.draw_list
+            .prepare(&graphics_objects, shared.frame_index());
+        self.draw_list.sort();
+        Ok(())
+    }
+
+    fn build_commands(
+        &mut self,
+        graphics_objects: Arc<GraphicsObjects>,
+        shared: Arc<Self::SharedData>,
+        cmd_buffer: &mut Box<CmdBuffer>,
+        _: Self::PreProcessed,
+    ) -> Result<Self::Output, HaltPolicy> {
+        Ok(self.record(&graphics_objects, shared.frame_index(), cmd_buffer))
+    }
+
+    fn postprocess(
+        &mut self,
+        _: Arc<GraphicsObjects>,
+        _: Arc<Self::SharedData>,
+        output: Self::Output,
+    ) {
+        if let (Err(err), Some(error_handler)) = (output, self.error_handler.as_mut()) {
+            error_handler(err);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    /// The camera uniforms couldn't be allocated
+    Allocation(MemoryAllocatorError),
+    Canvas(CanvasError),
+    Pass(PassError),
+    /// The pipelines' layouts have no set with the camera set's number
+    MissingCameraSet(u32),
+    Vulkan(Validated<VulkanError>),
+    Validation(Box<ValidationError>),
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Allocation(err) => write!(f, "failed to allocate camera uniforms: {err}"),
+            Self::Canvas(err) => write!(f, "failed to render the scene: {err}"),
+            Self::Pass(err) => write!(f, "failed to render the scene: {err}"),
+            Self::MissingCameraSet(set) => {
+                write!(
+                    f,
+                    "the pipeline layout has no descriptor set {set} for the camera"
+                )
+            }
+            Self::Vulkan(err) => write!(f, "failed to create the camera descriptor set: {err}"),
+            Self::Validation(err) => write!(f, "failed to record the scene: {err}"),
+        }
+    }
+}
+
+impl Error for SceneError {}
+
+impl From<MemoryAllocatorError> for SceneError {
+    fn from(err: MemoryAllocatorError) -> Self {
+        Self::Allocation(err)
+    }
+}
+
+impl From<CanvasError> for SceneError {
+    fn from(err: CanvasError) -> Self {
+        Self::Canvas(err)
+    }
+}
+
+impl From<PassError> for SceneError {
+    fn from(err: PassError) -> Self {
+        Self::Pass(err)
+    }
+}
+
+impl From<Validated<VulkanError>> for SceneError {
+    fn from(err: Validated<VulkanError>) -> Self {
+        Self::Vulkan(err)
+    }
+}
+
+impl From<Box<ValidationError>> for SceneError {
+    fn from(err: Box<ValidationError>) -> Self {
+        Self::Validation(err)
+    }
+}
@@ -5,14 +5,20 @@ use crate::{
     GraphicsObjects,
 };
 
+pub type SetupResult<T> = Result<
+    (
+        Arc<<T as SubmitSystem>::SharedType>,
+        <T as SubmitSystem>::SetupType,
+        <T as SubmitSystem>::CmdBufType,
+    ),
+    HaltPolicy,
+>;
+
 pub trait SubmitSystem {
     type SharedType;
     type SetupType;
     type CmdBufType;
-    fn setup(
-        &mut self,
-        graphics_objects: Arc<GraphicsObjects>,
-    ) -> Result<(Arc<Self::SharedType>, Self::SetupType, Self::CmdBufType), HaltPolicy>;
+    fn setup(&mut self, graphics_objects: Arc<GraphicsObjects>) -> SetupResult<Self>;
     fn submit(
         &mut self,
         graphics_objects: Arc<GraphicsObjects>,
@@ -20,4 +26,4 @@ pub trait SubmitSystem {
         setup_data: Self::SetupType,
         shared_data: Arc<Self::SharedType>,
     );
-}
\ No newline at end of file
+}
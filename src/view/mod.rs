@@ -0,0 +1,397 @@
+use std::f32::consts::FRAC_PI_2;
+
+use nalgebra::{
+    Isometry3,
+    Perspective3,
+    Point3,
+    Vector3,
+};
+use vulkano::{
+    buffer::{
+        allocator::SubbufferAllocator,
+        BufferContents,
+        Subbuffer,
+    },
+    memory::allocator::MemoryAllocatorError,
+    pipeline::{
+        graphics::viewport::{
+            Scissor,
+            Viewport,
+        },
+        GraphicsPipeline,
+    },
+    ValidationError,
+};
+
+use crate::renderpass::{
+    set_viewport_and_scissor,
+    CmdBuffer,
+};
+
+/// A rectangle of the render area in fractions of its size, from `[0, 0]` at the top left to
+/// `[1, 1]` at the bottom right
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ViewRect {
+    pub offset: [f32; 2],
+    pub extent: [f32; 2],
+}
+
+impl ViewRect {
+    pub const FULL: Self = Self {
+        offset: [0.0, 0.0],
+        extent: [1.0, 1.0],
+    };
+
+    pub fn new(offset: [f32; 2], extent: [f32; 2]) -> Self {
+        Self { offset, extent }
+    }
+
+    /// The pixel offset and extent of the rectangle in a render area of `area_extent` at
+    /// `area_offset`, at least one pixel large and inside the render area. Neighbouring rectangles
+    /// share their edges exactly. `None` for an empty render area, which has no pixel to put the
+    /// rectangle in
+    pub fn pixels(
+        &self,
+        area_offset: [u32; 2],
+        area_extent: [u32; 2],
+    ) -> Option<([u32; 2], [u32; 2])> {
+        if area_extent.contains(&0) {
+            return None;
+        }
+
+        let edge = |fraction: f32, size: u32| ((fraction * size as f32).round() as u32).min(size);
+
+        let mut offset = [0; 2];
+        let mut extent = [0; 2];
+        for axis in 0..2 {
+            let size = area_extent[axis];
+            let start = edge(self.offset[axis], size).min(size - 1);
+            let end = edge(self.offset[axis] + self.extent[axis], size).max(start + 1);
+
+            offset[axis] = area_offset[axis] + start;
+            extent[axis] = end - start;
+        }
+
+        Some((offset, extent))
+    }
+
+    /// Whether `position`, in the same fractions, is inside the rectangle
+    pub fn contains(&self, position: [f32; 2]) -> bool {
+        (0..2).all(|axis| {
+            position[axis] >= self.offset[axis]
+                && position[axis] < self.offset[axis] + self.extent[axis]
+        })
+    }
+}
+
+/// A perspective camera, with the aspect ratio left to the view it's rendered in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Camera {
+    pub view: Isometry3<f32>,
+    /// Vertical field of view in radians
+    pub fov_y: f32,
+    pub near: f32,
+    pub far: f32,
+}
+
+impl Camera {
+    pub fn look_at(eye: Point3<f32>, target: Point3<f32>, up: Vector3<f32>) -> Self {
+        Self {
+            view: Isometry3::look_at_rh(&eye, &target, &up),
+            fov_y: FRAC_PI_2,
+            near: 0.01,
+            far: 100.0,
+        }
+    }
+
+    pub fn uniform(&self, aspect_ratio: f32) -> CameraUniform {
+        let projection = Perspective3::new(aspect_ratio, self.fov_y, self.near, self.far);
+
+        CameraUniform {
+            view: self.view.to_homogeneous().as_slice().try_into().unwrap(),
+            proj: projection.to_homogeneous().as_slice().try_into().unwrap(),
+        }
+    }
+}
+
+/// A camera's matrices as laid out in a uniform buffer, column major
+#[derive(BufferContents, Clone, Copy, Debug)]
+#[repr(C)]
+pub struct CameraUniform {
+    pub view: [f32; 16],
+    pub proj: [f32; 16],
+}
+
+/// A camera rendered into part of the render area
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct View {
+    pub rect: ViewRect,
+    pub camera: Camera,
+    /// Clears the view's rectangle before drawing it, for views drawn over others such as a
+    /// picture-in-picture inset
+    pub clear: bool,
+}
+
+impl View {
+    pub fn new(rect: ViewRect, camera: Camera) -> Self {
+        Self {
+            rect,
+            camera,
+            clear: false,
+        }
+    }
+
+    pub fn with_clear(mut self) -> Self {
+        self.clear = true;
+        self
+    }
+}
+
+/// The views one canvas is rendered with, drawn in order so later views end up on top
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ViewLayout {
+    pub views: Vec<View>,
+}
+
+impl ViewLayout {
+    pub fn single(camera: Camera) -> Self {
+        Self {
+            views: vec![View::new(ViewRect::FULL, camera)],
+        }
+    }
+
+    /// One column per camera, left to right
+    pub fn split_screen(cameras: &[Camera]) -> Self {
+        Self::grid(cameras, cameras.len().max(1))
+    }
+
+    /// The cameras laid out row by row in `columns` columns, e.g. 2 columns for an editor's quad
+    /// view
+    pub fn grid(cameras: &[Camera], columns: usize) -> Self {
+        let columns = columns.max(1);
+        let rows = cameras.len().div_ceil(columns).max(1);
+        let extent = [1.0 / columns as f32, 1.0 / rows as f32];
+
+        let views = cameras
+            .iter()
+            .enumerate()
+            .map(|(index, &camera)| {
+                let offset = [
+                    (index % columns) as f32 * extent[0],
+                    (index / columns) as f32 * extent[1],
+                ];
+                View::new(ViewRect::new(offset, extent), camera)
+            })
+            .collect();
+
+        Self { views }
+    }
+
+    /// `main` over the whole render area with `inset` drawn on top of it in `rect`
+    pub fn picture_in_picture(main: Camera, inset: Camera, rect: ViewRect) -> Self {
+        Self {
+            views: vec![
+                View::new(ViewRect::FULL, main),
+                View::new(rect, inset).with_clear(),
+            ],
+        }
+    }
+
+    pub fn push(&mut self, view: View) -> &mut Self {
+        self.views.push(view);
+        self
+    }
+
+    /// The topmost view at `position`, in fractions of the render area, e.g. to route mouse
+    /// input to the view under the cursor
+    pub fn view_at(&self, position: [f32; 2]) -> Option<usize> {
+        self.views
+            .iter()
+            .rposition(|view| view.rect.contains(position))
+    }
+
+    /// Places every view in a render area of `area_extent` at `area_offset`, the same area the
+    /// render pass was begun with, and writes each camera's uniforms with the aspect ratio of its
+    /// view. An empty render area has no views
+    pub fn frame_views(
+        &self,
+        area_offset: [u32; 2],
+        area_extent: [u32; 2],
+        uniform_allocator: &SubbufferAllocator,
+    ) -> Result<Vec<FrameView>, MemoryAllocatorError> {
+        self.views
+            .iter()
+            .filter_map(|view| {
+                let (offset, extent) = view.rect.pixels(area_offset, area_extent)?;
+                Some((view, offset, extent))
+            })
+            .map(|(view, offset, extent)| {
+                let camera = uniform_allocator.allocate_sized()?;
+                *camera.write().unwrap() = view.camera.uniform(extent[0] as f32 / extent[1] as f32);
+
+                Ok(FrameView {
+                    offset,
+                    extent,
+                    clear: view.clear,
+                    camera,
+                })
+            })
+            .collect()
+    }
+}
+
+/// A [`View`] placed in a frame's render area, from [`ViewLayout::frame_views`]
+#[derive(Clone, Debug)]
+pub struct FrameView {
+    pub offset: [u32; 2],
+    pub extent: [u32; 2],
+    /// See [`View::clear`]
+    pub clear: bool,
+    /// This frame's uniforms of the view's camera
+    pub camera: Subbuffer<CameraUniform>,
+}
+
+impl FrameView {
+    pub fn viewport(&self) -> Viewport {
+        Viewport {
+            offset: self.offset.map(|value| value as f32),
+            extent: self.extent.map(|value| value as f32),
+            depth_range: 0.0..=1.0,
+        }
+    }
+
+    pub fn scissor(&self) -> Scissor {
+        Scissor {
+            offset: self.offset,
+            extent: self.extent,
+        }
+    }
+
+    /// Sets viewport and scissor 0 to the view with [`set_viewport_and_scissor`], so pipelines
+    /// drawn in the view need both as dynamic state. Inside a canvas' render pass,
+    /// [`RenderPassController::begin_view`](crate::canvas::RenderPassController::begin_view)
+    /// also handles [`FrameView::clear`]
+    pub fn apply<'a>(
+        &self,
+        cmd_buf: &'a mut CmdBuffer,
+        bound_pipeline: Option<&GraphicsPipeline>,
+    ) -> Result<&'a mut CmdBuffer, Box<ValidationError>> {
+        set_viewport_and_scissor(cmd_buf, self.viewport(), self.scissor(), bound_pipeline)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn camera() -> Camera {
+        Camera::look_at(Point3::new(0.0, 0.0, 1.0), Point3::origin(), Vector3::y())
+    }
+
+    fn rects(layout: &ViewLayout) -> Vec<ViewRect> {
+        layout.views.iter().map(|view| view.rect).collect()
+    }
+
+    #[test]
+    fn full_rect_covers_the_render_area() {
+        assert_eq!(
+            ViewRect::FULL.pixels([0, 0], [1920, 1080]),
+            Some(([0, 0], [1920, 1080]))
+        );
+    }
+
+    #[test]
+    fn neighbouring_rects_share_their_edges() {
+        let left = ViewRect::new([0.0, 0.0], [0.5, 1.0]).pixels([0, 0], [101, 100]);
+        let right = ViewRect::new([0.5, 0.0], [0.5, 1.0]).pixels([0, 0], [101, 100]);
+        assert_eq!(left, Some(([0, 0], [51, 100])));
+        assert_eq!(right, Some(([51, 0], [50, 100])));
+
+        let layout = ViewLayout::split_screen(&[camera(); 3]);
+        let pixels: Vec<_> = layout
+            .views
+            .iter()
+            .filter_map(|view| view.rect.pixels([0, 0], [100, 10]))
+            .collect();
+        assert_eq!(
+            pixels,
+            [([0, 0], [33, 10]), ([33, 0], [34, 10]), ([67, 0], [33, 10])]
+        );
+    }
+
+    #[test]
+    fn rects_are_at_least_one_pixel_inside_the_render_area() {
+        assert_eq!(
+            ViewRect::new([0.5, 0.5], [0.0, 0.0]).pixels([0, 0], [100, 100]),
+            Some(([50, 50], [1, 1]))
+        );
+        assert_eq!(
+            ViewRect::new([1.0, 0.9], [0.5, 0.5]).pixels([0, 0], [100, 100]),
+            Some(([99, 90], [1, 10]))
+        );
+    }
+
+    #[test]
+    fn empty_render_areas_have_no_pixels() {
+        assert_eq!(ViewRect::FULL.pixels([0, 0], [0, 0]), None);
+        assert_eq!(ViewRect::FULL.pixels([10, 10], [100, 0]), None);
+    }
+
+    #[test]
+    fn rects_are_placed_at_the_render_area_offset() {
+        assert_eq!(
+            ViewRect::FULL.pixels([20, 10], [100, 50]),
+            Some(([20, 10], [100, 50]))
+        );
+        assert_eq!(
+            ViewRect::new([0.5, 0.5], [0.5, 0.5]).pixels([20, 10], [100, 50]),
+            Some(([70, 35], [50, 25]))
+        );
+        assert_eq!(
+            ViewRect::new([1.0, 1.0], [0.5, 0.5]).pixels([20, 10], [100, 50]),
+            Some(([119, 59], [1, 1]))
+        );
+    }
+
+    #[test]
+    fn grid_fills_rows_first() {
+        let layout = ViewLayout::grid(&[camera(); 3], 2);
+
+        assert_eq!(
+            rects(&layout),
+            [
+                ViewRect::new([0.0, 0.0], [0.5, 0.5]),
+                ViewRect::new([0.5, 0.0], [0.5, 0.5]),
+                ViewRect::new([0.0, 0.5], [0.5, 0.5]),
+            ]
+        );
+    }
+
+    #[test]
+    fn grid_handles_degenerate_counts() {
+        assert!(ViewLayout::grid(&[], 2).views.is_empty());
+        assert!(ViewLayout::split_screen(&[]).views.is_empty());
+
+        let layout = ViewLayout::grid(&[camera(); 2], 0);
+        assert_eq!(
+            rects(&layout),
+            [
+                ViewRect::new([0.0, 0.0], [1.0, 0.5]),
+                ViewRect::new([0.0, 0.5], [1.0, 0.5]),
+            ]
+        );
+    }
+
+    #[test]
+    fn view_at_finds_the_topmost_view() {
+        let layout = ViewLayout::picture_in_picture(
+            camera(),
+            camera(),
+            ViewRect::new([0.75, 0.0], [0.25, 0.25]),
+        );
+
+        assert_eq!(layout.view_at([0.8, 0.1]), Some(1));
+        assert_eq!(layout.view_at([0.5, 0.5]), Some(0));
+        assert_eq!(layout.view_at([1.0, 0.5]), None);
+    }
+}
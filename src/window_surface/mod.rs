@@ -1,67 +1,286 @@
-use std::sync::Arc;
+#[cfg(feature = "winit")]
+use std::cmp::Reverse;
+use std::{
+    any::Any,
+    error::Error,
+    fmt,
+    sync::Arc,
+    time::{
+        Duration,
+        Instant,
+    },
+};
 
+use raw_window_handle::{
+    HasRawDisplayHandle,
+    HasRawWindowHandle,
+};
+use slotmap::{
+    new_key_type,
+    SlotMap,
+};
 use vulkano::{
-    device::Device,
-    format::Format,
+    command_buffer::{
+        CommandBufferExecError,
+        PrimaryCommandBufferAbstract,
+    },
+    device::{
+        DeviceOwned,
+        Queue,
+    },
+    format::{
+        Format,
+        NumericFormat,
+    },
     image::{
+        view::ImageView,
         Image,
         ImageUsage,
     },
-    render_pass::Framebuffer,
+    pipeline::graphics::viewport::Viewport,
+    render_pass::{
+        Framebuffer,
+        FramebufferCreateInfo,
+        RenderPass,
+    },
     swapchain::{
+        acquire_next_image,
+        ColorSpace,
+        CompositeAlpha,
+        CompositeAlphas,
+        PresentMode,
         Surface,
         Swapchain,
+        SwapchainAcquireFuture,
         SwapchainCreateInfo,
+        SwapchainPresentInfo,
     },
     sync::{
         future::FenceSignalFuture,
         GpuFuture,
     },
+    Validated,
+    ValidationError,
+    VulkanError,
 };
+#[cfg(feature = "winit")]
 use winit::{
-    dpi::PhysicalSize,
-    event_loop::EventLoop,
+    error::OsError,
+    event::WindowEvent,
+    event_loop::EventLoopWindowTarget,
+    monitor::{
+        MonitorHandle,
+        VideoMode,
+    },
     window::{
+        Fullscreen,
         Window,
         WindowBuilder,
     },
 };
 
+use crate::{
+    frame::{
+        FramePacing,
+        FrameStats,
+        GpuTimer,
+    },
+    renderpass::CmdBuffer,
+    GraphicsObjects,
+};
+
+pub type FrameFence = Arc<FenceSignalFuture<Box<dyn GpuFuture + Send>>>;
+
+/// Called with the new extent whenever the swapchain of a [`WindowSurface`] is recreated
+pub type ResizeListener = Box<dyn FnMut([u32; 2]) + Send>;
+
+new_key_type! {
+    pub struct ResizeListenerKey;
+}
+
+/// Options used when creating the swapchain of a [`WindowSurface`]
+#[derive(Clone, Debug)]
+pub struct SurfaceConfig {
+    /// The preferred present mode. If the surface doesn't support it, the closest supported
+    /// mode is used instead, see [`choose_present_mode`]
+    pub present_mode: PresentMode,
+    /// Which surface format and color space to prefer, see [`choose_surface_format`]
+    pub format: SurfaceFormatPreference,
+    /// The preferred composite alpha mode, see [`choose_composite_alpha`]. Anything other than
+    /// `Opaque` also creates winit windows with transparency enabled
+    pub composite_alpha: CompositeAlpha,
+    /// Frame rate cap and latency mode, can be changed later through [`WindowSurface::pacing`]
+    pub pacing: FramePacing,
+}
+
+impl SurfaceConfig {
+    pub fn transparent(&self) -> bool {
+        self.composite_alpha != CompositeAlpha::Opaque
+    }
+}
+
+impl Default for SurfaceConfig {
+    fn default() -> Self {
+        Self {
+            present_mode: PresentMode::Fifo,
+            format: SurfaceFormatPreference::Srgb,
+            composite_alpha: CompositeAlpha::Opaque,
+            pacing: FramePacing::default(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SurfaceFormatPreference {
+    /// An 8-bit sRGB format in the `SrgbNonLinear` color space
+    #[default]
+    Srgb,
+    /// Any format in the HDR10 (`Hdr10St2084`) color space, otherwise the same as `Srgb`
+    Hdr10,
+    /// A 16-bit float format in the scRGB (`ExtendedSrgbLinear`) color space, otherwise the same
+    /// as `Srgb`
+    ScRgb,
+    /// Whichever format the surface reports first
+    First,
+}
+
+/// What a [`WindowSurface`] presents to
+pub(crate) enum SurfaceTarget {
+    #[cfg(feature = "winit")]
+    Winit(Arc<Window>),
+    /// A window only known by its raw handles, sized by the caller
+    Raw { extent: [u32; 2] },
+}
+
+impl SurfaceTarget {
+    fn inner_extent(&self) -> [u32; 2] {
+        match self {
+            #[cfg(feature = "winit")]
+            Self::Winit(window) => window.inner_size().into(),
+            Self::Raw { extent } => *extent,
+        }
+    }
+
+    fn is_minimized(&self) -> bool {
+        match self {
+            #[cfg(feature = "winit")]
+            Self::Winit(window) => window.is_minimized() == Some(true),
+            Self::Raw { .. } => false,
+        }
+    }
+}
+
 pub struct WindowSurface {
-    pub window: Arc<Window>,
+    target: SurfaceTarget,
     pub swapchain: Arc<Swapchain>,
     pub images: Vec<Arc<Image>>,
+    pub image_views: Vec<Arc<ImageView>>,
+    /// One framebuffer per swapchain image, only built once a render pass has been given with
+    /// [`WindowSurface::set_render_pass`]
     pub framebuffers: Vec<Arc<Framebuffer>>,
+    pub viewport: Viewport,
     pub recreate_swapchain: bool,
-    pub previous_frame_fences: Vec<Option<Arc<FenceSignalFuture<Box<dyn GpuFuture + Send>>>>>,
+    /// The fence of the last frame submitted in each frame-in-flight slot
+    pub previous_frame_fences: Vec<Option<FrameFence>>,
     pub num_frames_in_flight: usize,
+    /// The slot of the most recently submitted frame
     pub previous_frame_index: usize,
+    /// The slot the next acquired frame will use
+    pub frame_index: usize,
     pub surface_image_format: Format,
+    pub surface_color_space: ColorSpace,
+    /// The present mode the swapchain will use the next time it is (re)created
+    pub present_mode: PresentMode,
+    pub supported_present_modes: Vec<PresentMode>,
+    pub pacing: FramePacing,
+    frame_stats: FrameStats,
+    frame_timings: Vec<Option<FrameTiming>>,
+    last_frame_start: Option<Instant>,
+    gpu_timer: Option<GpuTimer>,
+    render_pass: Option<Arc<RenderPass>>,
+    occluded: bool,
+    resize_listeners: SlotMap<ResizeListenerKey, ResizeListener>,
 }
 
 impl WindowSurface {
-    pub fn new<ELT>(event_loop: &EventLoop<ELT>, device: Arc<Device>) -> Self {
-        let window = WindowBuilder::new()
-            .with_title("New window")
-            .with_inner_size(PhysicalSize::new(400, 400))
-            .build(event_loop)
-            .unwrap();
+    #[cfg(feature = "winit")]
+    /// Builds a window and creates its swapchain on the renderer's device. Fails if the
+    /// graphics queue can't present to the new window's surface
+    pub fn new<ELT>(
+        event_loop: &EventLoopWindowTarget<ELT>,
+        window_builder: WindowBuilder,
+        graphics_objects: &GraphicsObjects,
+        config: SurfaceConfig,
+    ) -> Result<Self, WindowSurfaceError> {
+        let window = Arc::new(
+            window_builder
+                .with_transparent(config.transparent())
+                .build(event_loop)?,
+        );
+
+        let surface =
+            Surface::from_window(graphics_objects.device.instance().clone(), window.clone())?;
+        check_surface_support(&surface, graphics_objects)?;
+
+        Self::from_surface(
+            SurfaceTarget::Winit(window),
+            surface,
+            graphics_objects,
+            config,
+        )
+    }
 
-        let window = Arc::new(window);
+    /// Creates a surface for a window owned by something other than winit, such as a viewport
+    /// embedded in another UI toolkit. The size can't be queried from the handles, so the caller
+    /// provides it here and keeps it up to date with [`WindowSurface::set_extent`]
+    pub fn from_raw_handle<W>(
+        handle: Arc<W>,
+        extent: [u32; 2],
+        graphics_objects: &GraphicsObjects,
+        config: SurfaceConfig,
+    ) -> Result<Self, WindowSurfaceError>
+    where
+        W: HasRawWindowHandle + HasRawDisplayHandle + Any + Send + Sync,
+    {
+        let surface = Surface::from_window(graphics_objects.device.instance().clone(), handle)?;
+        check_surface_support(&surface, graphics_objects)?;
 
-        let surface = Surface::from_window(device.instance().clone(), window.clone()).unwrap();
+        Self::from_surface(
+            SurfaceTarget::Raw { extent },
+            surface,
+            graphics_objects,
+            config,
+        )
+    }
+
+    /// Creates the swapchain for a target whose surface has already been created
+    pub(crate) fn from_surface(
+        target: SurfaceTarget,
+        surface: Arc<Surface>,
+        graphics_objects: &GraphicsObjects,
+        config: SurfaceConfig,
+    ) -> Result<Self, WindowSurfaceError> {
+        let device = graphics_objects.device.clone();
+        let num_frames_in_flight = graphics_objects.num_frames_in_flight;
+
+        let (surface_image_format, surface_color_space) = choose_surface_format(
+            &device
+                .physical_device()
+                .surface_formats(&surface, Default::default())?,
+            config.format,
+        );
 
-        let surface_image_format = device
+        let supported_present_modes: Vec<PresentMode> = device
             .physical_device()
-            .surface_formats(&surface, Default::default())
-            .unwrap()[0]
-            .0;
+            .surface_present_modes(&surface, Default::default())?
+            .collect();
+
+        let present_mode = choose_present_mode(&supported_present_modes, config.present_mode);
 
         let (swapchain, images) = {
             let surface_capabilities = device
                 .physical_device()
-                .surface_capabilities(&surface, Default::default())
-                .unwrap();
+                .surface_capabilities(&surface, Default::default())?;
 
             Swapchain::new(
                 device.clone(),
@@ -69,32 +288,934 @@ impl WindowSurface {
                 SwapchainCreateInfo {
                     min_image_count: surface_capabilities.min_image_count.max(2),
                     image_format: surface_image_format,
-                    image_extent: window.inner_size().into(),
+                    image_color_space: surface_color_space,
+                    image_extent: target.inner_extent(),
                     image_usage: ImageUsage::COLOR_ATTACHMENT | ImageUsage::TRANSFER_DST,
-                    composite_alpha: surface_capabilities
-                        .supported_composite_alpha
-                        .into_iter()
-                        .next()
-                        .unwrap(),
-                    present_mode: vulkano::swapchain::PresentMode::Fifo,
+                    composite_alpha: choose_composite_alpha(
+                        surface_capabilities.supported_composite_alpha,
+                        config.composite_alpha,
+                    ),
+                    present_mode,
                     ..Default::default()
                 },
-            )
-            .unwrap()
+            )?
         };
 
-        let previous_frame_fences = (0..images.len()).map(|_| None).collect::<Vec<_>>();
+        let previous_frame_fences = (0..num_frames_in_flight).map(|_| None).collect::<Vec<_>>();
+        let image_views = images
+            .iter()
+            .map(|image| ImageView::new_default(image.clone()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let extent = swapchain.image_extent();
+        let gpu_timer = GpuTimer::new(
+            device,
+            graphics_objects.graphics_queue.queue_family_index(),
+            num_frames_in_flight,
+        );
 
-        Self {
-            window,
+        Ok(Self {
+            target,
             swapchain,
             images,
+            image_views,
             framebuffers: Vec::new(),
+            viewport: Viewport {
+                offset: [0.0, 0.0],
+                extent: [extent[0] as f32, extent[1] as f32],
+                depth_range: 0.0..=1.0,
+            },
             previous_frame_fences,
             recreate_swapchain: true,
-            num_frames_in_flight: 0,
+            num_frames_in_flight,
             previous_frame_index: 0,
+            frame_index: 0,
             surface_image_format,
+            surface_color_space,
+            present_mode,
+            supported_present_modes,
+            pacing: config.pacing,
+            frame_stats: FrameStats::default(),
+            frame_timings: (0..num_frames_in_flight).map(|_| None).collect(),
+            last_frame_start: None,
+            gpu_timer,
+            render_pass: None,
+            occluded: false,
+            resize_listeners: SlotMap::with_key(),
+        })
+    }
+
+    /// The winit window, `None` for surfaces created from raw handles
+    #[cfg(feature = "winit")]
+    pub fn window(&self) -> Option<&Arc<Window>> {
+        match &self.target {
+            SurfaceTarget::Winit(window) => Some(window),
+            SurfaceTarget::Raw { .. } => None,
+        }
+    }
+
+    /// Flags the swapchain for recreation on window events that change the surface size, and
+    /// tracks whether the window is occluded
+    #[cfg(feature = "winit")]
+    pub fn handle_event(&mut self, event: &WindowEvent) {
+        match event {
+            WindowEvent::Resized(_) | WindowEvent::ScaleFactorChanged { .. } => {
+                self.recreate_swapchain = true
+            }
+            WindowEvent::Occluded(occluded) => self.set_occluded(*occluded),
+            _ => (),
+        }
+    }
+
+    /// The size of the window's drawable area in physical pixels
+    pub fn inner_extent(&self) -> [u32; 2] {
+        self.target.inner_extent()
+    }
+
+    /// Updates the size of a surface created from raw handles, flagging the swapchain for
+    /// recreation if it changed. Winit windows report their own size, so this does nothing for
+    /// them
+    pub fn set_extent(&mut self, new_extent: [u32; 2]) {
+        match &mut self.target {
+            SurfaceTarget::Raw { extent } if *extent != new_extent => {
+                *extent = new_extent;
+                self.recreate_swapchain = true;
+            }
+            _ => (),
+        }
+    }
+
+    /// Marks the window as fully hidden, or visible again. Winit windows are kept up to date by
+    /// [`WindowSurface::handle_event`]
+    pub fn set_occluded(&mut self, occluded: bool) {
+        self.occluded = occluded;
+    }
+
+    pub fn visibility(&self) -> WindowVisibility {
+        if self.inner_extent().contains(&0) || self.target.is_minimized() {
+            WindowVisibility::Minimized
+        } else if self.occluded {
+            WindowVisibility::Occluded
+        } else {
+            WindowVisibility::Visible
+        }
+    }
+
+    /// Whether a frame rendered now would be seen, frames are skipped by
+    /// [`WindowSurface::acquire_next_image`] otherwise
+    pub fn is_presentable(&self) -> bool {
+        self.visibility() == WindowVisibility::Visible
+    }
+
+    /// The extent the swapchain should have right now. Taken from the surface when it reports
+    /// one, as the window's inner size can lag behind right after a display mode switch
+    pub fn surface_extent(&self) -> Result<[u32; 2], Validated<VulkanError>> {
+        let capabilities = self
+            .swapchain
+            .device()
+            .physical_device()
+            .surface_capabilities(self.swapchain.surface(), Default::default())?;
+
+        let extent = capabilities
+            .current_extent
+            .unwrap_or_else(|| self.inner_extent());
+
+        Ok([
+            extent[0].clamp(
+                capabilities.min_image_extent[0],
+                capabilities.max_image_extent[0],
+            ),
+            extent[1].clamp(
+                capabilities.min_image_extent[1],
+                capabilities.max_image_extent[1],
+            ),
+        ])
+    }
+
+    /// Recreates the swapchain to fit the surface's current size, along with the image views,
+    /// framebuffers and viewport depending on it, then notifies the resize listeners
+    pub fn recreate(&mut self) -> Result<(), Validated<VulkanError>> {
+        let image_extent = self.surface_extent()?;
+        let (swapchain, images) = self
+            .swapchain
+            .recreate(self.swapchain_create_info(image_extent))?;
+
+        self.swapchain = swapchain;
+        self.images = images;
+        self.recreate_swapchain = false;
+
+        self.window_size_dependent_setup()?;
+
+        let extent = self.swapchain.image_extent();
+        for listener in self.resize_listeners.values_mut() {
+            listener(extent);
+        }
+
+        Ok(())
+    }
+
+    /// Switches between windowed, borderless and exclusive fullscreen. The swapchain is recreated
+    /// with the surface's new extent before the next frame is acquired. Does nothing for surfaces
+    /// created from raw handles
+    #[cfg(feature = "winit")]
+    pub fn set_fullscreen(&mut self, fullscreen: Option<Fullscreen>) {
+        if let Some(window) = self.window() {
+            window.set_fullscreen(fullscreen);
+            self.recreate_swapchain = true;
+        }
+    }
+
+    #[cfg(feature = "winit")]
+    pub fn fullscreen(&self) -> Option<Fullscreen> {
+        self.window().and_then(|window| window.fullscreen())
+    }
+
+    /// Switches to exclusive fullscreen on `monitor`, or the window's current monitor, using the
+    /// video mode closest to `resolution`. Returns false if the monitor has no video modes, or if
+    /// the surface was created from raw handles
+    #[cfg(feature = "winit")]
+    pub fn set_resolution(&mut self, monitor: Option<MonitorHandle>, resolution: [u32; 2]) -> bool {
+        let Some(window) = self.window() else {
+            return false;
+        };
+        let Some(monitor) = monitor.or_else(|| window.current_monitor()) else {
+            return false;
+        };
+
+        match choose_video_mode(&monitor, resolution) {
+            None => false,
+            Some(video_mode) => {
+                self.set_fullscreen(Some(Fullscreen::Exclusive(video_mode)));
+                true
+            }
         }
     }
+
+    /// Sets the render pass the swapchain framebuffers are built for, and builds them
+    pub fn set_render_pass(
+        &mut self,
+        render_pass: Option<Arc<RenderPass>>,
+    ) -> Result<(), Validated<VulkanError>> {
+        self.render_pass = render_pass;
+        self.window_size_dependent_setup()
+    }
+
+    pub fn add_resize_listener(
+        &mut self,
+        listener: impl FnMut([u32; 2]) + Send + 'static,
+    ) -> ResizeListenerKey {
+        self.resize_listeners.insert(Box::new(listener))
+    }
+
+    pub fn remove_resize_listener(&mut self, key: ResizeListenerKey) -> Option<ResizeListener> {
+        self.resize_listeners.remove(key)
+    }
+
+    fn window_size_dependent_setup(&mut self) -> Result<(), Validated<VulkanError>> {
+        let extent = self.swapchain.image_extent();
+        self.viewport.extent = [extent[0] as f32, extent[1] as f32];
+
+        self.image_views = self
+            .images
+            .iter()
+            .map(|image| ImageView::new_default(image.clone()))
+            .collect::<Result<_, _>>()?;
+
+        self.framebuffers = match self.render_pass.as_ref() {
+            None => Vec::new(),
+            Some(render_pass) => self
+                .image_views
+                .iter()
+                .map(|view| {
+                    Framebuffer::new(
+                        render_pass.clone(),
+                        FramebufferCreateInfo {
+                            attachments: vec![view.clone()],
+                            ..Default::default()
+                        },
+                    )
+                })
+                .collect::<Result<_, _>>()?,
+        };
+
+        Ok(())
+    }
+
+    /// Applies the frame rate cap, waits for the frame that last used the next frame-in-flight
+    /// slot to finish (or the previous frame in low latency mode), then acquires the next
+    /// swapchain image, recreating the swapchain first if it was flagged. Suboptimal and out of
+    /// date swapchains are flagged to be recreated on the next call
+    pub fn acquire_next_image(&mut self) -> Result<AcquiredFrame, AcquireError> {
+        match self.visibility() {
+            WindowVisibility::Visible => (),
+            WindowVisibility::Minimized => return Err(AcquireError::ZeroExtent),
+            WindowVisibility::Occluded => return Err(AcquireError::Occluded),
+        }
+
+        let frame_index = self.frame_index;
+        let started = self.pacing.wait(self.last_frame_start);
+
+        self.poll_frame_fences();
+
+        if self.pacing.low_latency {
+            if let Some(fence) = self.previous_frame_fences[self.previous_frame_index].as_ref() {
+                fence.wait(None)?;
+                self.poll_frame_fences();
+            }
+        }
+
+        if let Some(fence) = self.previous_frame_fences[frame_index].as_ref() {
+            fence.wait(None)?;
+            self.poll_frame_fences();
+        }
+
+        self.collect_frame_stats(frame_index);
+
+        if self.recreate_swapchain {
+            self.recreate()?;
+        }
+
+        let (image_index, suboptimal, acquire_future) =
+            match acquire_next_image(self.swapchain.clone(), None).map_err(Validated::unwrap) {
+                Ok(r) => r,
+                Err(VulkanError::OutOfDate) => {
+                    self.recreate_swapchain = true;
+                    return Err(AcquireError::OutOfDate);
+                }
+                Err(err) => return Err(AcquireError::Vulkan(Validated::Error(err))),
+            };
+
+        if suboptimal {
+            self.recreate_swapchain = true;
+        }
+
+        self.frame_timings[frame_index] = Some(FrameTiming {
+            acquired: Instant::now(),
+            frame_interval: self
+                .last_frame_start
+                .map_or(Duration::ZERO, |last| started - last),
+            cpu_time: Duration::ZERO,
+            submitted: None,
+            signaled: None,
+        });
+        self.last_frame_start = Some(started);
+
+        Ok(AcquiredFrame {
+            frame_index,
+            image_index: image_index as usize,
+            acquire_future,
+        })
+    }
+
+    /// Executes the command buffer after the previously submitted frame, once the image is
+    /// acquired, then presents it and advances to the next frame-in-flight slot. The slot is
+    /// advanced even if this fails. An out of date swapchain isn't an error, it's flagged to be
+    /// recreated on the next acquire
+    pub fn present(
+        &mut self,
+        queue: Arc<Queue>,
+        command_buffer: Arc<impl PrimaryCommandBufferAbstract + 'static>,
+        frame: AcquiredFrame,
+    ) -> Result<(), PresentError> {
+        let AcquiredFrame {
+            frame_index,
+            image_index,
+            acquire_future,
+        } = frame;
+
+        if let Some(timing) = self.frame_timings[frame_index].as_mut() {
+            let submitted = Instant::now();
+            timing.cpu_time = submitted - timing.acquired;
+            timing.submitted = Some(submitted);
+        }
+
+        let previous_future = match self.previous_frame_fences[self.previous_frame_index].clone() {
+            None => {
+                let mut now = vulkano::sync::now(queue.device().clone());
+                now.cleanup_finished();
+
+                now.boxed_send()
+            }
+            Some(mut fence) => {
+                fence.cleanup_finished();
+                fence.boxed_send()
+            }
+        };
+
+        let result = previous_future
+            .join(acquire_future)
+            .then_execute(queue.clone(), command_buffer)
+            .map_err(PresentError::Execute)
+            .and_then(|future| {
+                future
+                    .then_swapchain_present(
+                        queue,
+                        SwapchainPresentInfo::swapchain_image_index(
+                            self.swapchain.clone(),
+                            image_index as u32,
+                        ),
+                    )
+                    .boxed_send()
+                    .then_signal_fence_and_flush()
+                    .map_err(PresentError::Vulkan)
+            });
+
+        let (fence, result) = match result {
+            Ok(fence) => (Some(Arc::new(fence)), Ok(())),
+            Err(PresentError::Vulkan(Validated::Error(VulkanError::OutOfDate))) => {
+                self.recreate_swapchain = true;
+                (None, Ok(()))
+            }
+            Err(err) => (None, Err(err)),
+        };
+
+        if fence.is_none() {
+            self.frame_timings[frame_index] = None;
+        }
+        self.previous_frame_fences[frame_index] = fence;
+        self.previous_frame_index = frame_index;
+        self.frame_index = (frame_index + 1) % self.num_frames_in_flight;
+        self.poll_frame_fences();
+
+        result
+    }
+
+    /// Timings of the most recent frame known to have finished executing
+    pub fn frame_stats(&self) -> FrameStats {
+        self.frame_stats
+    }
+
+    /// Writes the timestamp the frame's GPU time is measured from, should be the first command
+    /// recorded for the frame. Does nothing if the queue doesn't support timestamps
+    pub fn begin_gpu_timer(
+        &mut self,
+        cmd_buf: &mut CmdBuffer,
+        frame_index: usize,
+    ) -> Result<(), Box<ValidationError>> {
+        match self.gpu_timer.as_mut() {
+            Some(timer) => timer.begin(cmd_buf, frame_index),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes the timestamp the frame's GPU time is measured to, should be the last command
+    /// recorded for the frame
+    pub fn end_gpu_timer(
+        &mut self,
+        cmd_buf: &mut CmdBuffer,
+        frame_index: usize,
+    ) -> Result<(), Box<ValidationError>> {
+        match self.gpu_timer.as_mut() {
+            Some(timer) => timer.end(cmd_buf, frame_index),
+            None => Ok(()),
+        }
+    }
+
+    fn collect_frame_stats(&mut self, frame_index: usize) {
+        let Some(timing) = self.frame_timings[frame_index].take() else {
+            return;
+        };
+
+        self.frame_stats = FrameStats {
+            frame_interval: timing.frame_interval,
+            cpu_time: timing.cpu_time,
+            gpu_time: self
+                .gpu_timer
+                .as_mut()
+                .and_then(|timer| timer.read(frame_index)),
+            submit_to_signal_bound: match (timing.submitted, timing.signaled) {
+                (Some(submitted), Some(signaled)) => signaled.saturating_duration_since(submitted),
+                _ => Duration::ZERO,
+            },
+        };
+    }
+
+    /// Records when the fences of submitted frames are first seen signaled, for
+    /// [`FrameStats::submit_to_signal_bound`]
+    fn poll_frame_fences(&mut self) {
+        let now = Instant::now();
+        for (timing, fence) in self
+            .frame_timings
+            .iter_mut()
+            .zip(&self.previous_frame_fences)
+        {
+            let (Some(timing), Some(fence)) = (timing.as_mut(), fence.as_ref()) else {
+                continue;
+            };
+
+            if timing.submitted.is_some()
+                && timing.signaled.is_none()
+                && fence.is_signaled().unwrap_or(false)
+            {
+                timing.signaled = Some(now);
+            }
+        }
+    }
+
+    /// Blocks until every frame submitted to this window has finished executing. Waits on every
+    /// frame even if one fails, and returns the first error
+    pub fn wait_for_frames(&mut self) -> Result<(), Validated<VulkanError>> {
+        let mut result = Ok(());
+        for fence in self.previous_frame_fences.iter_mut() {
+            if let Some(fence) = fence.take() {
+                let waited = fence.wait(None);
+                result = result.and(waited);
+            }
+        }
+
+        result
+    }
+
+    /// Requests a new present mode. The closest supported mode is picked, and the swapchain is
+    /// flagged for recreation if it differs from the one currently in use
+    pub fn set_present_mode(&mut self, preferred: PresentMode) -> PresentMode {
+        self.present_mode = choose_present_mode(&self.supported_present_modes, preferred);
+        if self.present_mode != self.swapchain.present_mode() {
+            self.recreate_swapchain = true;
+        }
+
+        self.present_mode
+    }
+
+    /// Switches between `Fifo` (vsync on) and `Mailbox`, or `Immediate` if mailbox isn't
+    /// supported (vsync off)
+    pub fn set_vsync(&mut self, enabled: bool) -> PresentMode {
+        self.set_present_mode(match enabled {
+            true => PresentMode::Fifo,
+            false => PresentMode::Mailbox,
+        })
+    }
+
+    /// Whether the compositor uses the alpha channel of presented images. Anything blitted to a
+    /// transparent window should carry alpha, and be premultiplied for `PreMultiplied`
+    pub fn is_transparent(&self) -> bool {
+        self.swapchain.composite_alpha() != CompositeAlpha::Opaque
+    }
+
+    pub fn vsync(&self) -> bool {
+        matches!(
+            self.present_mode,
+            PresentMode::Fifo | PresentMode::FifoRelaxed
+        )
+    }
+
+    /// The create info the swapchain should be recreated with, using the current present mode
+    pub fn swapchain_create_info(&self, image_extent: [u32; 2]) -> SwapchainCreateInfo {
+        SwapchainCreateInfo {
+            image_extent,
+            present_mode: self.present_mode,
+            ..self.swapchain.create_info()
+        }
+    }
+}
+
+/// Picks `preferred` if the surface supports it, otherwise the closest alternative.
+/// `Mailbox` and `Immediate` fall back to each other before `Fifo`, `FifoRelaxed` falls back to
+/// `Fifo`, which every surface is required to support
+pub fn choose_present_mode(supported: &[PresentMode], preferred: PresentMode) -> PresentMode {
+    let candidates: &[PresentMode] = match preferred {
+        PresentMode::Mailbox => &[PresentMode::Mailbox, PresentMode::Immediate],
+        PresentMode::Immediate => &[PresentMode::Immediate, PresentMode::Mailbox],
+        _ => &[preferred],
+    };
+
+    candidates
+        .iter()
+        .copied()
+        .find(|mode| supported.contains(mode))
+        .unwrap_or(PresentMode::Fifo)
+}
+
+fn check_surface_support(
+    surface: &Surface,
+    graphics_objects: &GraphicsObjects,
+) -> Result<(), WindowSurfaceError> {
+    let supported = graphics_objects.device.physical_device().surface_support(
+        graphics_objects.graphics_queue.queue_family_index(),
+        surface,
+    )?;
+
+    match supported {
+        true => Ok(()),
+        false => Err(WindowSurfaceError::SurfaceNotSupported),
+    }
+}
+
+#[derive(Debug)]
+pub enum WindowSurfaceError {
+    /// The window couldn't be built
+    #[cfg(feature = "winit")]
+    Os(OsError),
+    Vulkan(Validated<VulkanError>),
+    /// The renderer's graphics queue can't present to the window's surface
+    SurfaceNotSupported,
+}
+
+impl fmt::Display for WindowSurfaceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "winit")]
+            Self::Os(err) => write!(f, "failed to build window: {err}"),
+            Self::Vulkan(err) => write!(f, "failed to create window surface: {err}"),
+            Self::SurfaceNotSupported => {
+                write!(f, "the graphics queue can't present to the window surface")
+            }
+        }
+    }
+}
+
+impl Error for WindowSurfaceError {}
+
+#[cfg(feature = "winit")]
+impl From<OsError> for WindowSurfaceError {
+    fn from(err: OsError) -> Self {
+        Self::Os(err)
+    }
+}
+
+impl From<Validated<VulkanError>> for WindowSurfaceError {
+    fn from(err: Validated<VulkanError>) -> Self {
+        Self::Vulkan(err)
+    }
+}
+
+#[derive(Debug)]
+pub enum PresentError {
+    /// The command buffer couldn't be executed, e.g. because it failed validation
+    Execute(CommandBufferExecError),
+    Vulkan(Validated<VulkanError>),
+}
+
+impl fmt::Display for PresentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Execute(err) => write!(f, "failed to execute the frame: {err}"),
+            Self::Vulkan(err) => write!(f, "failed to present the frame: {err}"),
+        }
+    }
+}
+
+impl Error for PresentError {}
+
+impl From<CommandBufferExecError> for PresentError {
+    fn from(err: CommandBufferExecError) -> Self {
+        Self::Execute(err)
+    }
+}
+
+impl From<Validated<VulkanError>> for PresentError {
+    fn from(err: Validated<VulkanError>) -> Self {
+        Self::Vulkan(err)
+    }
+}
+
+struct FrameTiming {
+    acquired: Instant,
+    frame_interval: Duration,
+    cpu_time: Duration,
+    submitted: Option<Instant>,
+    /// When the frame's fence was first seen signaled
+    signaled: Option<Instant>,
+}
+
+/// A swapchain image acquired by [`WindowSurface::acquire_next_image`], to be handed back to
+/// [`WindowSurface::present`]
+pub struct AcquiredFrame {
+    /// The frame-in-flight slot, in `0..num_frames_in_flight`. Resources used by the frame can be
+    /// reused once the same slot comes around again
+    pub frame_index: usize,
+    /// The swapchain image to render to
+    pub image_index: usize,
+    pub acquire_future: SwapchainAcquireFuture,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WindowVisibility {
+    Visible,
+    /// The window is minimized or otherwise has a zero extent
+    Minimized,
+    /// The window is fully covered or on another workspace
+    Occluded,
+}
+
+#[derive(Debug)]
+pub enum AcquireError {
+    /// The window has no area to present to, e.g. because it is minimized
+    ZeroExtent,
+    /// The window is fully hidden, so the frame is skipped
+    Occluded,
+    /// The swapchain is out of date, it will be recreated on the next acquire
+    OutOfDate,
+    Vulkan(Validated<VulkanError>),
+}
+
+impl fmt::Display for AcquireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ZeroExtent => write!(f, "the window has a zero extent"),
+            Self::Occluded => write!(f, "the window is occluded"),
+            Self::OutOfDate => write!(f, "the swapchain is out of date"),
+            Self::Vulkan(err) => write!(f, "failed to acquire next image: {err}"),
+        }
+    }
+}
+
+impl Error for AcquireError {}
+
+impl From<Validated<VulkanError>> for AcquireError {
+    fn from(err: Validated<VulkanError>) -> Self {
+        Self::Vulkan(err)
+    }
+}
+
+/// Picks the video mode of `monitor` whose size is closest to `resolution`, preferring higher
+/// bit depths and refresh rates among equally close modes
+#[cfg(feature = "winit")]
+pub fn choose_video_mode(monitor: &MonitorHandle, resolution: [u32; 2]) -> Option<VideoMode> {
+    monitor.video_modes().min_by_key(|mode| {
+        let size = mode.size();
+        let distance =
+            size.width.abs_diff(resolution[0]) as u64 + size.height.abs_diff(resolution[1]) as u64;
+        (
+            distance,
+            Reverse(mode.bit_depth()),
+            Reverse(mode.refresh_rate_millihertz()),
+        )
+    })
+}
+
+/// Picks `preferred` if the surface supports it. `PreMultiplied` and `PostMultiplied` fall back
+/// to each other and then to `Inherit`, before settling on `Opaque` or whatever else is supported
+pub fn choose_composite_alpha(
+    supported: CompositeAlphas,
+    preferred: CompositeAlpha,
+) -> CompositeAlpha {
+    let candidates: &[CompositeAlpha] = match preferred {
+        CompositeAlpha::PreMultiplied => &[
+            CompositeAlpha::PreMultiplied,
+            CompositeAlpha::PostMultiplied,
+            CompositeAlpha::Inherit,
+        ],
+        CompositeAlpha::PostMultiplied => &[
+            CompositeAlpha::PostMultiplied,
+            CompositeAlpha::PreMultiplied,
+            CompositeAlpha::Inherit,
+        ],
+        _ => &[preferred],
+    };
+
+    candidates
+        .iter()
+        .copied()
+        .chain([CompositeAlpha::Opaque])
+        .find(|alpha| supported.contains_enum(*alpha))
+        .or(supported.into_iter().next())
+        .expect("surface supports no composite alpha modes")
+}
+
+/// Picks a format and color space from those reported by the surface. HDR preferences fall back
+/// to `Srgb` when the display doesn't expose a matching color space, and `Srgb` falls back to the
+/// first reported format when no 8-bit sRGB format is available
+pub fn choose_surface_format(
+    supported: &[(Format, ColorSpace)],
+    preference: SurfaceFormatPreference,
+) -> (Format, ColorSpace) {
+    let hdr = match preference {
+        SurfaceFormatPreference::Hdr10 => supported
+            .iter()
+            .find(|(_, color_space)| *color_space == ColorSpace::Hdr10St2084),
+        SurfaceFormatPreference::ScRgb => supported.iter().find(|(format, color_space)| {
+            *color_space == ColorSpace::ExtendedSrgbLinear
+                && format.numeric_format_color() == Some(NumericFormat::SFLOAT)
+                && format.components() == [16, 16, 16, 16]
+        }),
+        SurfaceFormatPreference::First => supported.first(),
+        SurfaceFormatPreference::Srgb => None,
+    };
+
+    let srgb = || {
+        supported.iter().find(|(format, color_space)| {
+            *color_space == ColorSpace::SrgbNonLinear
+                && format.numeric_format_color() == Some(NumericFormat::SRGB)
+                && format.components() == [8, 8, 8, 8]
+        })
+    };
+
+    *hdr.or_else(srgb)
+        .or(supported.first())
+        .expect("surface reports no formats")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn present_mode_prefers_the_requested_mode() {
+        let supported = [
+            PresentMode::Fifo,
+            PresentMode::Mailbox,
+            PresentMode::Immediate,
+        ];
+
+        assert_eq!(
+            choose_present_mode(&supported, PresentMode::Immediate),
+            PresentMode::Immediate
+        );
+        assert_eq!(
+            choose_present_mode(&supported, PresentMode::Mailbox),
+            PresentMode::Mailbox
+        );
+    }
+
+    #[test]
+    fn present_mode_falls_back_between_mailbox_and_immediate() {
+        assert_eq!(
+            choose_present_mode(
+                &[PresentMode::Fifo, PresentMode::Immediate],
+                PresentMode::Mailbox
+            ),
+            PresentMode::Immediate
+        );
+        assert_eq!(
+            choose_present_mode(
+                &[PresentMode::Fifo, PresentMode::Mailbox],
+                PresentMode::Immediate
+            ),
+            PresentMode::Mailbox
+        );
+    }
+
+    #[test]
+    fn present_mode_falls_back_to_fifo() {
+        let supported = [PresentMode::Fifo];
+
+        for preferred in [
+            PresentMode::Mailbox,
+            PresentMode::Immediate,
+            PresentMode::FifoRelaxed,
+            PresentMode::Fifo,
+        ] {
+            assert_eq!(
+                choose_present_mode(&supported, preferred),
+                PresentMode::Fifo
+            );
+        }
+        assert_eq!(
+            choose_present_mode(&[], PresentMode::Mailbox),
+            PresentMode::Fifo
+        );
+    }
+
+    #[test]
+    fn surface_format_prefers_8_bit_srgb() {
+        let supported = [
+            (Format::B8G8R8A8_UNORM, ColorSpace::SrgbNonLinear),
+            (Format::A2B10G10R10_UNORM_PACK32, ColorSpace::SrgbNonLinear),
+            (Format::B8G8R8A8_SRGB, ColorSpace::SrgbNonLinear),
+        ];
+
+        assert_eq!(
+            choose_surface_format(&supported, SurfaceFormatPreference::Srgb),
+            (Format::B8G8R8A8_SRGB, ColorSpace::SrgbNonLinear)
+        );
+    }
+
+    #[test]
+    fn surface_format_falls_back_to_the_first_format_without_srgb() {
+        let supported = [
+            (Format::B8G8R8A8_UNORM, ColorSpace::SrgbNonLinear),
+            (Format::R8G8B8A8_UNORM, ColorSpace::SrgbNonLinear),
+        ];
+
+        assert_eq!(
+            choose_surface_format(&supported, SurfaceFormatPreference::Srgb),
+            (Format::B8G8R8A8_UNORM, ColorSpace::SrgbNonLinear)
+        );
+    }
+
+    #[test]
+    fn surface_format_picks_hdr_color_spaces() {
+        let supported = [
+            (Format::B8G8R8A8_SRGB, ColorSpace::SrgbNonLinear),
+            (Format::A2B10G10R10_UNORM_PACK32, ColorSpace::Hdr10St2084),
+            (Format::R16G16B16A16_UNORM, ColorSpace::ExtendedSrgbLinear),
+            (Format::R16G16B16A16_SFLOAT, ColorSpace::ExtendedSrgbLinear),
+        ];
+
+        assert_eq!(
+            choose_surface_format(&supported, SurfaceFormatPreference::Hdr10),
+            (Format::A2B10G10R10_UNORM_PACK32, ColorSpace::Hdr10St2084)
+        );
+        assert_eq!(
+            choose_surface_format(&supported, SurfaceFormatPreference::ScRgb),
+            (Format::R16G16B16A16_SFLOAT, ColorSpace::ExtendedSrgbLinear)
+        );
+    }
+
+    #[test]
+    fn surface_format_falls_back_to_srgb_without_hdr() {
+        let supported = [
+            (Format::B8G8R8A8_UNORM, ColorSpace::SrgbNonLinear),
+            (Format::B8G8R8A8_SRGB, ColorSpace::SrgbNonLinear),
+            (Format::R16G16B16A16_UNORM, ColorSpace::ExtendedSrgbLinear),
+        ];
+
+        for preference in [
+            SurfaceFormatPreference::Hdr10,
+            SurfaceFormatPreference::ScRgb,
+        ] {
+            assert_eq!(
+                choose_surface_format(&supported, preference),
+                (Format::B8G8R8A8_SRGB, ColorSpace::SrgbNonLinear)
+            );
+        }
+        assert_eq!(
+            choose_surface_format(&supported, SurfaceFormatPreference::First),
+            (Format::B8G8R8A8_UNORM, ColorSpace::SrgbNonLinear)
+        );
+    }
+
+    #[test]
+    fn composite_alpha_prefers_the_requested_mode() {
+        let supported = CompositeAlphas::OPAQUE | CompositeAlphas::PRE_MULTIPLIED;
+
+        assert_eq!(
+            choose_composite_alpha(supported, CompositeAlpha::PreMultiplied),
+            CompositeAlpha::PreMultiplied
+        );
+        assert_eq!(
+            choose_composite_alpha(supported, CompositeAlpha::Opaque),
+            CompositeAlpha::Opaque
+        );
+    }
+
+    #[test]
+    fn composite_alpha_falls_back_through_transparent_modes() {
+        assert_eq!(
+            choose_composite_alpha(
+                CompositeAlphas::OPAQUE | CompositeAlphas::POST_MULTIPLIED,
+                CompositeAlpha::PreMultiplied
+            ),
+            CompositeAlpha::PostMultiplied
+        );
+        assert_eq!(
+            choose_composite_alpha(
+                CompositeAlphas::OPAQUE | CompositeAlphas::INHERIT,
+                CompositeAlpha::PostMultiplied
+            ),
+            CompositeAlpha::Inherit
+        );
+        assert_eq!(
+            choose_composite_alpha(CompositeAlphas::OPAQUE, CompositeAlpha::PreMultiplied),
+            CompositeAlpha::Opaque
+        );
+    }
+
+    #[test]
+    fn composite_alpha_uses_any_supported_mode_without_opaque() {
+        assert_eq!(
+            choose_composite_alpha(CompositeAlphas::INHERIT, CompositeAlpha::Opaque),
+            CompositeAlpha::Inherit
+        );
+    }
 }
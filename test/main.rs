@@ -1,27 +1,51 @@
 use std::{
     collections::HashMap,
+    f32::consts::TAU,
     io::Read,
     sync::Arc,
     time::Instant,
 };
 
 use aspen_renderer::{
-    canvas::Canvas,
+    canvas::{
+        Canvas,
+        CanvasResizePolicy,
+    },
+    composite::{
+        CompositePass,
+        CompositeStaging,
+        ScaleMode,
+    },
+    drawable::{
+        DrawItem,
+        DrawList,
+    },
+    frame::DynamicResolution,
     render_system::DefaultRenderSystem,
+    scene::ScenePass,
+    view::{
+        Camera,
+        ViewLayout,
+        ViewRect,
+    },
+    window_surface::SurfaceConfig,
     Renderer,
 };
+use nalgebra::{
+    Point3,
+    Vector3,
+};
 use parking_lot::Mutex;
 use passes::{
-    circles::CirclesRenderPass,
-    present::PresentSystem,
-    window_blit::WindowBlitRenderPass,
+    circles::Circles,
+    present::{
+        PresentSystem,
+        SharedInfo,
+    },
 };
 use vulkano::{
     buffer::{
-        allocator::{
-            SubbufferAllocator,
-            SubbufferAllocatorCreateInfo,
-        },
+        allocator::SubbufferAllocator,
         Buffer,
         BufferContents,
         BufferCreateInfo,
@@ -35,11 +59,7 @@ use vulkano::{
         DescriptorType,
     },
     format::Format,
-    image::{
-        ImageCreateInfo,
-        ImageType,
-        ImageUsage,
-    },
+    image::SampleCount,
     memory::allocator::{
         AllocationCreateInfo,
         MemoryTypeFilter,
@@ -87,13 +107,21 @@ use vulkano::{
     },
 };
 use winit::{
+    dpi::PhysicalSize,
     event::{
+        ElementState,
         Event,
+        KeyEvent,
         WindowEvent,
     },
-    event_loop::{
-        ControlFlow,
-        EventLoopBuilder,
+    event_loop::EventLoopBuilder,
+    keyboard::{
+        KeyCode,
+        PhysicalKey,
+    },
+    window::{
+        Fullscreen,
+        WindowBuilder,
     },
 };
 
@@ -121,6 +149,50 @@ pub struct IndexedMesh {
     pub ibo: Subbuffer<[u32]>,
 }
 
+/// A single camera, split-screen, picture-in-picture or a quad view of the circles
+fn views(layout: usize) -> ViewLayout {
+    let up = Vector3::new(0.0, -1.0, 0.0);
+    let target = Point3::new(0.0, 0.0, 4.5);
+    let front = Camera::look_at(Point3::new(1.5, 0.0, -9.0), Point3::origin(), up);
+    let side = Camera::look_at(Point3::new(-14.0, 0.0, 4.5), target, up);
+    let top = Camera::look_at(Point3::new(0.0, -14.0, 4.5), target, Vector3::z());
+    let back = Camera::look_at(Point3::new(0.0, 0.0, 20.0), target, up);
+
+    match layout {
+        0 => ViewLayout::single(front),
+        1 => ViewLayout::split_screen(&[front, side]),
+        2 => ViewLayout::picture_in_picture(front, top, ViewRect::new([0.65, 0.05], [0.3, 0.3])),
+        _ => ViewLayout::grid(&[front, side, top, back], 2),
+    }
+}
+
+/// Draws the circles into `canvas` with the given views
+fn scene_pass(
+    canvas: &Arc<Canvas>,
+    pipeline: &Arc<GraphicsPipeline>,
+    mesh: &IndexedMesh,
+    elapsed_time: f32,
+    views: ViewLayout,
+    transparent: bool,
+) -> ScenePass<SharedInfo> {
+    let mut draw_list = DrawList::new();
+    draw_list.push(DrawItem::new(
+        pipeline.clone(),
+        Circles::new(elapsed_time, mesh),
+    ));
+
+    // Transparent windows show whatever alpha the canvas ends up with
+    let clear_color = match transparent {
+        true => [0.0; 4],
+        false => [0.2, 0.2, 0.2, 1.0],
+    };
+
+    ScenePass::new(canvas.clone(), views, draw_list)
+        .with_camera_set(1)
+        .with_clear_value("color", clear_color)
+        .with_error_handler(|err| println!("{err}"))
+}
+
 enum GlobalEvent {
     Update,
 }
@@ -134,72 +206,23 @@ fn main() {
 
     let (mut renderer, _main_window_id) = Renderer::new(&event_loop);
 
-    let pass_ubo = Arc::new(Mutex::new(SubbufferAllocator::new(
-        renderer.allocator().clone(),
-        SubbufferAllocatorCreateInfo {
-            buffer_usage: BufferUsage::UNIFORM_BUFFER,
-            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
-                | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
-            ..Default::default()
-        },
-    )));
-
-    let obj_ubo = Arc::new(Mutex::new(SubbufferAllocator::new(
-        renderer.allocator().clone(),
-        SubbufferAllocatorCreateInfo {
-            buffer_usage: BufferUsage::UNIFORM_BUFFER,
-            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
-                | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
-            ..Default::default()
-        },
-    )));
-
     //let (surface_format, num_frames_in_flight) = {
     //    let guard = renderer.windows.get(&main_window_id).unwrap().lock().unwrap();
     //    (guard.surface_image_format, guard.num_frames_in_flight)
     //};
 
-    let renderpass = vulkano::single_pass_renderpass!(
+    let samples = SampleCount::Sample4;
+    let canvas = Canvas::multisampled(
         renderer.device().clone(),
-        attachments: {
-            color: {
-                format: Format::R8G8B8A8_SRGB,
-                samples: 1,
-                load_op: Clear,
-                store_op: Store,
-            },
-            depth: {
-                format: Format::D32_SFLOAT,
-                samples: 1,
-                load_op: Clear,
-                store_op: DontCare,
-            }
-        },
-        pass: {
-            color: [color],
-            depth_stencil: {depth},
-        },
+        &[("color", Format::R8G8B8A8_SRGB)],
+        Some(("depth", Format::D32_SFLOAT)),
+        samples,
     )
     .unwrap();
+    let renderpass = canvas.render_pass().unwrap();
 
-    let canvas = Canvas::empty(
-        renderpass.clone(),
-        [
-            ImageCreateInfo {
-                image_type: ImageType::Dim2d,
-                format: Format::R8G8B8A8_SRGB,
-                usage: ImageUsage::COLOR_ATTACHMENT | ImageUsage::TRANSFER_SRC,
-                ..Default::default()
-            },
-            ImageCreateInfo {
-                image_type: ImageType::Dim2d,
-                format: Format::D32_SFLOAT,
-                usage: ImageUsage::DEPTH_STENCIL_ATTACHMENT | ImageUsage::TRANSIENT_ATTACHMENT,
-                ..Default::default()
-            },
-        ]
-        .into(),
-    );
+    // Every window renders into its own instance of the canvas
+    renderer.canvases.register("scene", canvas.clone());
 
     let pipeline = {
         let vs = {
@@ -366,12 +389,17 @@ fn main() {
                     front_face: FrontFace::CounterClockwise,
                     ..Default::default()
                 }),
-                multisample_state: Some(MultisampleState::default()),
+                multisample_state: Some(MultisampleState {
+                    rasterization_samples: samples,
+                    ..Default::default()
+                }),
                 color_blend_state: Some(ColorBlendState::with_attachment_states(
                     subpass.num_color_attachments(),
                     ColorBlendAttachmentState::default(),
                 )),
-                dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                dynamic_state: [DynamicState::Viewport, DynamicState::Scissor]
+                    .into_iter()
+                    .collect(),
                 depth_stencil_state: Some(DepthStencilState {
                     depth: Some(DepthState::simple()),
                     ..Default::default()
@@ -394,8 +422,8 @@ fn main() {
         let radius = 0.5;
         for i in 0..num_points {
             let i = i as f32;
-            let x = radius * ((6.283 / num_points as f32) * i - (6.283 / 4.0)).cos();
-            let y = radius * ((6.283 / num_points as f32) * i - (6.283 / 4.0)).sin();
+            let x = radius * ((TAU / num_points as f32) * i - (TAU / 4.0)).cos();
+            let y = radius * ((TAU / num_points as f32) * i - (TAU / 4.0)).sin();
 
             verts.push(PosColVertex {
                 position: [x, y],
@@ -450,46 +478,217 @@ fn main() {
     let meshes: HashMap<&'static str, IndexedMesh> = [("hex", hex_mesh)].into();
 
     let start_time = Instant::now();
+    let mut dynamic_resolution: Option<Arc<Mutex<DynamicResolution>>> = None;
+    let mut scale_mode = ScaleMode::Letterbox;
+    let mut view_layout = 0;
+    // Passes are rebuilt every frame, so each window keeps its composite staging images here
+    let mut staging: HashMap<_, CompositeStaging> = HashMap::new();
 
     let proxy = event_loop.create_proxy();
     event_loop
         .run(move |event, elwt| {
-            elwt.set_control_flow(ControlFlow::Poll);
+            elwt.set_control_flow(renderer.control_flow());
             match event {
                 Event::WindowEvent { window_id, event } => match event {
                     WindowEvent::CloseRequested => {
-                        _ = renderer.windows.remove(&window_id);
-                        if renderer.windows.len() == 0 {
+                        if let Err(err) = renderer.destroy_window(window_id) {
+                            println!("failed to wait for the window's frames: {err}");
+                        }
+                        staging.remove(&window_id);
+                        if renderer.windows.is_empty() {
                             elwt.exit()
                         }
                     }
-                    WindowEvent::Resized(_) => {
-                        let windows = &mut renderer.windows;
-                        let mut window = windows.get_mut(&window_id).unwrap().lock();
-                        window.recreate_swapchain = true;
+                    event @ (WindowEvent::Resized(_)
+                    | WindowEvent::ScaleFactorChanged { .. }
+                    | WindowEvent::Occluded(_)) => {
+                        if let Some(window) = renderer.windows.get(&window_id) {
+                            window.lock().handle_event(&event);
+                        }
+                    }
+                    WindowEvent::KeyboardInput {
+                        event:
+                            KeyEvent {
+                                physical_key: PhysicalKey::Code(KeyCode::KeyV),
+                                state: ElementState::Pressed,
+                                repeat: false,
+                                ..
+                            },
+                        ..
+                    } => {
+                        let mut window = renderer.windows.get(&window_id).unwrap().lock();
+                        let vsync = !window.vsync();
+                        let present_mode = window.set_vsync(vsync);
+                        println!("vsync: {vsync} ({present_mode:?})");
+                    }
+                    WindowEvent::KeyboardInput {
+                        event:
+                            KeyEvent {
+                                physical_key: PhysicalKey::Code(KeyCode::KeyF),
+                                state: ElementState::Pressed,
+                                ..
+                            },
+                        ..
+                    } => {
+                        let window = renderer.windows.get(&window_id).unwrap().lock();
+                        println!("{:#?}", window.frame_stats());
+                    }
+                    WindowEvent::KeyboardInput {
+                        event:
+                            KeyEvent {
+                                physical_key: PhysicalKey::Code(KeyCode::F11),
+                                state: ElementState::Pressed,
+                                repeat: false,
+                                ..
+                            },
+                        ..
+                    } => {
+                        let fullscreen = match renderer.windows[&window_id].lock().fullscreen() {
+                            Some(_) => None,
+                            None => Some(Fullscreen::Borderless(None)),
+                        };
+                        renderer.set_fullscreen(window_id, fullscreen);
+                    }
+                    WindowEvent::KeyboardInput {
+                        event:
+                            KeyEvent {
+                                physical_key: PhysicalKey::Code(KeyCode::KeyD),
+                                state: ElementState::Pressed,
+                                repeat: false,
+                                ..
+                            },
+                        ..
+                    } => {
+                        dynamic_resolution = match dynamic_resolution.take() {
+                            Some(_) => {
+                                // Back to the policy picked with R
+                                let policy = canvas.resize_policy();
+                                for instance in renderer.canvases.instances("scene") {
+                                    instance.set_resize_policy(policy);
+                                }
+                                None
+                            }
+                            None => Some(Arc::new(Mutex::new(DynamicResolution::default()))),
+                        };
+                        println!("dynamic resolution: {}", dynamic_resolution.is_some());
+                    }
+                    WindowEvent::KeyboardInput {
+                        event:
+                            KeyEvent {
+                                physical_key: PhysicalKey::Code(KeyCode::KeyR),
+                                state: ElementState::Pressed,
+                                repeat: false,
+                                ..
+                            },
+                        ..
+                    } => {
+                        let policy = match canvas.resize_policy() {
+                            CanvasResizePolicy::FollowTarget => CanvasResizePolicy::Scale(0.5),
+                            CanvasResizePolicy::Scale(scale) if scale < 1.0 => {
+                                CanvasResizePolicy::Scale(1.5)
+                            }
+                            _ => CanvasResizePolicy::FollowTarget,
+                        };
+                        println!("canvas resize policy: {policy:?}");
+                        canvas.set_resize_policy(policy);
+                        for instance in renderer.canvases.instances("scene") {
+                            instance.set_resize_policy(policy);
+                        }
+                    }
+                    WindowEvent::KeyboardInput {
+                        event:
+                            KeyEvent {
+                                physical_key: PhysicalKey::Code(KeyCode::KeyL),
+                                state: ElementState::Pressed,
+                                repeat: false,
+                                ..
+                            },
+                        ..
+                    } => {
+                        view_layout = (view_layout + 1) % 4;
+                        println!("view layout: {view_layout}");
+                    }
+                    WindowEvent::KeyboardInput {
+                        event:
+                            KeyEvent {
+                                physical_key: PhysicalKey::Code(KeyCode::KeyS),
+                                state: ElementState::Pressed,
+                                repeat: false,
+                                ..
+                            },
+                        ..
+                    } => {
+                        scale_mode = match scale_mode {
+                            ScaleMode::Stretch => ScaleMode::Letterbox,
+                            ScaleMode::Letterbox => ScaleMode::Integer,
+                            ScaleMode::Integer => ScaleMode::Crop,
+                            ScaleMode::Crop => ScaleMode::Stretch,
+                        };
+                        println!("scale mode: {scale_mode:?}");
+                    }
+                    WindowEvent::KeyboardInput {
+                        event:
+                            KeyEvent {
+                                physical_key: PhysicalKey::Code(KeyCode::KeyM),
+                                state: ElementState::Pressed,
+                                repeat: false,
+                                ..
+                            },
+                        ..
+                    } => {
+                        let mut report = renderer.canvas_memory_report([]);
+                        for (name, mesh) in &meshes {
+                            report
+                                .add_buffer(format!("{name} vertices"), mesh.vbo.buffer())
+                                .add_buffer(format!("{name} indices"), mesh.ibo.buffer());
+                        }
+                        print!("{report}");
+                    }
+                    WindowEvent::KeyboardInput {
+                        event:
+                            KeyEvent {
+                                physical_key: PhysicalKey::Code(KeyCode::KeyN),
+                                state: ElementState::Pressed,
+                                repeat: false,
+                                ..
+                            },
+                        ..
+                    } => {
+                        if let Err(err) = renderer.create_window(
+                            elwt,
+                            WindowBuilder::new()
+                                .with_title("New window")
+                                .with_inner_size(PhysicalSize::new(400, 400)),
+                            SurfaceConfig::default(),
+                        ) {
+                            println!("failed to create window: {err}");
+                        }
                     }
                     WindowEvent::RedrawRequested => {
+                        let Some(canvas) = renderer.window_canvas(window_id, "scene") else {
+                            return;
+                        };
                         let rendersystem = DefaultRenderSystem::new(
                             PresentSystem {
                                 window: renderer.windows.get(&window_id).unwrap().clone(),
+                                canvases: vec![canvas.clone()],
+                                dynamic_resolution: dynamic_resolution.clone(),
                             },
                             vec![
-                                CirclesRenderPass {
-                                    elapsed_time: Instant::now()
-                                        .duration_since(start_time)
-                                        .as_secs_f32(),
-                                    pass_ubo: pass_ubo.clone(),
-                                    obj_ubo: obj_ubo.clone(),
-                                    pipeline: pipeline.clone(),
-                                    meshes: meshes.clone(),
-                                    canvas: canvas.clone(),
-                                }
-                                .into(),
-                                WindowBlitRenderPass {
-                                    src_canvas: canvas.clone(),
-                                    attachment_index: 0,
-                                }
+                                scene_pass(
+                                    &canvas,
+                                    &pipeline,
+                                    &meshes["hex"],
+                                    start_time.elapsed().as_secs_f32(),
+                                    views(view_layout),
+                                    renderer.windows[&window_id].lock().is_transparent(),
+                                )
                                 .into(),
+                                CompositePass::new(canvas.clone(), "color")
+                                    .with_mode(scale_mode)
+                                    .with_staging(staging.entry(window_id).or_default().clone())
+                                    .with_error_handler(|err| println!("{err}"))
+                                    .into(),
                             ],
                         );
 
@@ -502,31 +701,35 @@ fn main() {
                     _ => (),
                 },
                 Event::AboutToWait => {
-                    let windows = &renderer.windows;
+                    let windows: Vec<_> = renderer
+                        .presentable_windows()
+                        .map(|(id, w)| (*id, w.clone()))
+                        .collect();
                     let barriers: Vec<_> = windows
-                        .iter()
-                        .map(|(_, w)| {
+                        .into_iter()
+                        .map(|(id, w)| {
+                            let canvas = renderer.window_canvas(id, "scene").unwrap();
                             let rendersystem = DefaultRenderSystem::new(
-                                PresentSystem { 
-                                    window: w.clone() 
+                                PresentSystem {
+                                    window: w.clone(),
+                                    canvases: vec![canvas.clone()],
+                                    dynamic_resolution: dynamic_resolution.clone(),
                                 },
                                 vec![
-                                    CirclesRenderPass {
-                                        elapsed_time: Instant::now()
-                                            .duration_since(start_time)
-                                            .as_secs_f32(),
-                                        pass_ubo: pass_ubo.clone(),
-                                        obj_ubo: obj_ubo.clone(),
-                                        pipeline: pipeline.clone(),
-                                        meshes: meshes.clone(),
-                                        canvas: canvas.clone(),
-                                    }
-                                    .into(),
-                                    WindowBlitRenderPass {
-                                        src_canvas: canvas.clone(),
-                                        attachment_index: 0,
-                                    }
+                                    scene_pass(
+                                        &canvas,
+                                        &pipeline,
+                                        &meshes["hex"],
+                                        start_time.elapsed().as_secs_f32(),
+                                        views(view_layout),
+                                        w.lock().is_transparent(),
+                                    )
                                     .into(),
+                                    CompositePass::new(canvas.clone(), "color")
+                                        .with_mode(scale_mode)
+                                        .with_staging(staging.entry(id).or_default().clone())
+                                        .with_error_handler(|err| println!("{err}"))
+                                        .into(),
                                 ],
                             );
 
@@ -1,3 +1,2 @@
 pub mod circles;
 pub mod present;
-pub mod window_blit;
@@ -1,12 +1,25 @@
 use std::sync::Arc;
 
 use aspen_renderer::{
+    canvas::Canvas,
+    composite::CompositeTarget,
+    frame::{
+        DynamicResolution,
+        FrameContext,
+    },
     renderpass::{
         CmdBuffer,
         HaltPolicy,
     },
-    submit_system::SubmitSystem,
-    window_surface::WindowSurface,
+    submit_system::{
+        SetupResult,
+        SubmitSystem,
+    },
+    window_surface::{
+        AcquireError,
+        AcquiredFrame,
+        WindowSurface,
+    },
     GraphicsObjects,
 };
 use parking_lot::Mutex;
@@ -15,31 +28,38 @@ use vulkano::{
         AutoCommandBufferBuilder,
         CommandBufferUsage,
     },
-    swapchain::{
-        acquire_next_image,
-        SwapchainAcquireFuture,
-        SwapchainCreateInfo,
-        SwapchainPresentInfo,
-    },
-    sync::GpuFuture,
-    Validated,
-    VulkanError,
+    image::Image,
 };
 
 pub struct PresentSystem {
     pub window: Arc<Mutex<WindowSurface>>,
+    /// Canvases resized to the window at the start of each frame
+    pub canvases: Vec<Arc<Canvas>>,
+    /// Drives the render scale of the canvases from the window's GPU frame time
+    pub dynamic_resolution: Option<Arc<Mutex<DynamicResolution>>>,
     //pub renderpass: Arc<RenderPass>
 }
 
 pub struct SharedInfo {
     pub window: Arc<Mutex<WindowSurface>>,
-    pub num_frames_in_flight: usize,
+    pub frame_index: usize,
     pub image_index: usize,
-    pub image_extent: [u32; 2],
+}
+
+impl CompositeTarget for SharedInfo {
+    fn composite_target(&self) -> Arc<Image> {
+        self.window.lock().images[self.image_index].clone()
+    }
+}
+
+impl FrameContext for SharedInfo {
+    fn frame_index(&self) -> usize {
+        self.frame_index
+    }
 }
 
 pub struct SetupData {
-    pub acquire_future: SwapchainAcquireFuture,
+    pub frame: AcquiredFrame,
 }
 
 impl SubmitSystem for PresentSystem {
@@ -47,72 +67,54 @@ impl SubmitSystem for PresentSystem {
     type SetupType = SetupData;
     type CmdBufType = Box<CmdBuffer>;
 
-    fn setup(
-        &mut self,
-        graphics_objects: Arc<GraphicsObjects>,
-    ) -> Result<(Arc<Self::SharedType>, Self::SetupType, Box<CmdBuffer>), HaltPolicy> {
+    fn setup(&mut self, graphics_objects: Arc<GraphicsObjects>) -> SetupResult<Self> {
         let mut window = self.window.lock();
-        let image_extent: [u32; 2] = window.window.inner_size().into();
 
-        if image_extent.contains(&0) {
-            return Err(HaltPolicy::HaltAll);
-        }
+        let frame = match window.acquire_next_image() {
+            Ok(val) => val,
+            Err(AcquireError::ZeroExtent | AcquireError::Occluded | AcquireError::OutOfDate) => {
+                return Err(HaltPolicy::HaltAll)
+            }
+            Err(e) => panic!("{e}"),
+        };
+        let image_extent = window.swapchain.image_extent();
 
-        let previous_frame_index = window.previous_frame_index as usize;
-        match window.previous_frame_fences[previous_frame_index].as_mut() {
-            Some(f) => f.cleanup_finished(),
-            None => (),
-        }
+        if let Some(dynamic_resolution) = &self.dynamic_resolution {
+            let mut dynamic_resolution = dynamic_resolution.lock();
+            dynamic_resolution.update(&window.frame_stats());
 
-        if window.recreate_swapchain {
-            let image_extent: [u32; 2] = window.window.inner_size().into();
-            let (new_swapchain, new_images) = window
-                .swapchain
-                .recreate(SwapchainCreateInfo {
-                    image_extent,
-                    ..window.swapchain.create_info()
-                })
-                .expect("failed to recreate swapchain");
-
-            window.swapchain = new_swapchain;
-            window.images = new_images;
-            window.num_frames_in_flight = window.images.len();
-            //let render_pass = self.renderpass.clone();
-            //window.image_size_dependent_setup(render_pass);
-            window.recreate_swapchain = false;
+            for canvas in &self.canvases {
+                canvas.set_resize_policy(dynamic_resolution.resize_policy());
+            }
         }
 
-        let (image_index, suboptimal, acquire_future) =
-            match acquire_next_image(window.swapchain.clone(), None).map_err(Validated::unwrap) {
-                Ok(r) => r,
-                Err(VulkanError::OutOfDate) => {
-                    window.recreate_swapchain = true;
-                    return Err(HaltPolicy::HaltAll);
-                }
-                Err(e) => panic!("failed to acquire next image: {e}"),
-            };
-
-        if suboptimal {
-            window.recreate_swapchain = true;
+        // The image is acquired, so the frame is still presented, without a canvas that fails
+        for canvas in &self.canvases {
+            if let Err(err) = canvas.begin_frame(image_extent, &graphics_objects) {
+                println!("{err}");
+            }
         }
 
-        let builder = Box::new(
+        let mut builder = Box::new(
             AutoCommandBufferBuilder::primary(
-                &graphics_objects.command_buffer_allocator,
+                &graphics_objects.frame_command_buffer_allocators[frame.frame_index],
                 graphics_objects.graphics_queue.queue_family_index(),
                 CommandBufferUsage::OneTimeSubmit,
             )
             .unwrap(),
         );
 
+        window
+            .begin_gpu_timer(&mut builder, frame.frame_index)
+            .unwrap();
+
         Ok((
             Arc::new(SharedInfo {
                 window: self.window.clone(),
-                num_frames_in_flight: window.num_frames_in_flight,
-                image_index: image_index as usize,
-                image_extent: [image_extent[0], image_extent[1]],
+                frame_index: frame.frame_index,
+                image_index: frame.image_index,
             }),
-            SetupData { acquire_future },
+            SetupData { frame },
             builder,
         ))
     }
@@ -120,62 +122,24 @@ impl SubmitSystem for PresentSystem {
     fn submit(
         &mut self,
         graphics_objects: Arc<GraphicsObjects>,
-        cmd_buffer: Box<CmdBuffer>,
+        mut cmd_buffer: Box<CmdBuffer>,
         setup_data: Self::SetupType,
-        shared: Arc<Self::SharedType>,
+        _: Arc<Self::SharedType>,
     ) {
         let mut window = self.window.lock();
 
-        let command_buffer = cmd_buffer.build().unwrap();
-
-        let previous_future = match window.previous_frame_fences[shared.image_index].clone() {
-            None => {
-                let mut now = vulkano::sync::now(graphics_objects.device.clone());
-                now.cleanup_finished();
-
-                now.boxed_send()
-            }
-            Some(mut fence) => {
-                fence.cleanup_finished();
-                fence.boxed_send()
-            }
-        };
+        window
+            .end_gpu_timer(&mut cmd_buffer, setup_data.frame.frame_index)
+            .unwrap();
 
-        let future = previous_future
-            .join(setup_data.acquire_future)
-            .then_execute(
-                graphics_objects.graphics_queue.clone(),
-                command_buffer.clone(),
-            )
-            .unwrap()
-            .then_swapchain_present(
-                graphics_objects.graphics_queue.clone(),
-                SwapchainPresentInfo::swapchain_image_index(
-                    window.swapchain.clone(),
-                    shared.image_index as u32,
-                ),
-            )
-            .boxed_send()
-            .then_signal_fence_and_flush();
-
-        window.previous_frame_fences[shared.image_index] = match future.map_err(Validated::unwrap) {
-            Ok(value) => Some(Arc::new(value)),
-            Err(VulkanError::OutOfDate) => {
-                let winextent = window.window.inner_size();
-                let swapextent: Vec<[u32; 3]> =
-                    window.images.iter().map(|image| image.extent()).collect();
-                println!(
-                    "Fence out of date.\nWindow size:\n{:#?}\nSwapchain image sizes:\n{:#?}",
-                    winextent, swapextent
-                );
-                None
-            }
-            Err(e) => {
-                println!("failed to flush future: {e}");
-                None
-            }
-        };
+        let command_buffer = cmd_buffer.build().unwrap();
 
-        window.previous_frame_index = shared.image_index;
+        if let Err(err) = window.present(
+            graphics_objects.graphics_queue.clone(),
+            command_buffer,
+            setup_data.frame,
+        ) {
+            println!("{err}");
+        }
     }
 }